@@ -1,26 +1,123 @@
-use crate::IMG_PATH;
+use crate::{BIBLIOGRAPHY, IMG_PATH, PREFER_VECTOR};
 use base64::{Engine as _, engine::general_purpose::STANDARD};
 use itertools::Itertools;
 use serde_json::Value;
-use std::sync::OnceLock;
+use std::sync::{Arc, LazyLock, Mutex};
 use std::{
-	collections::HashMap,
+	cell::RefCell,
+	collections::{HashMap, HashSet},
+	env,
 	fs::{self, File},
 	io::Write,
+	path::Path,
 };
 
 use crate::{katex, md, typ};
 
-static LANG: OnceLock<String> = OnceLock::new();
 static TEMPLATE: &str = "#import \"template.typ\": *\n#show: template\n\n";
+static TEMPLATE_WITH: &str = "#import \"template.typ\": *\n#show: template.with(";
+/// <MIME type, handler>, checked in registration order before the built-in types, shared by all threads
+static MIME_HANDLERS: LazyLock<Mutex<Vec<(String, MimeHandler)>>> = LazyLock::new(|| Mutex::new(Vec::new()));
 
-pub fn ipynb_parse(json: Value) -> String {
-	// https://nbformat.readthedocs.io/en/latest/format_description.html
-	let mut output = String::from(TEMPLATE);
-	LANG.set(json["metadata"]["language_info"]["name"].as_str().unwrap().into())
-		.unwrap();
+thread_local! {
+	static LANG: RefCell<String> = const { RefCell::new(String::new()) };
+	/// Saved widget state in notebook metadata, <model id, model>
+	static WIDGETS: RefCell<serde_json::Map<String, Value>> = RefCell::new(serde_json::Map::new());
+}
+
+/// Converts the data of a MIME bundle entry to Typst.
+///
+/// The argument is the raw value under the MIME key, which is a string, an array of strings or a JSON object.
+pub type MimeHandler = Arc<dyn Fn(&Value) -> String + Send + Sync>;
+
+/// Register a handler for a MIME type in `display_data` and `execute_result` outputs.
+///
+/// Registered handlers take precedence over the built-in ones, the first matching MIME type in registration order
+/// is used. Handlers are called without holding the registry, so they can register handlers themselves.
+pub fn register_mime_handler(mime: impl Into<String>, handler: impl Fn(&Value) -> String + Send + Sync + 'static) {
+	MIME_HANDLERS.lock().unwrap().push((mime.into(), Arc::new(handler)));
+}
+
+/// Register a notebook converted in the same batch, so links to it can be resolved.
+pub fn ipynb_register(path: &Path, json: &Value) {
+	let labels = match split_front_matter(json) {
+		Some((_, json)) => ipynb_labels(&json),
+		None => ipynb_labels(json),
+	};
+	md::register_notebook(path, labels);
+}
+
+/// Split the YAML front matter off the first cell, if it's a Markdown or raw cell.
+fn split_front_matter(json: &Value) -> Option<(String, Value)> {
+	let cell = json["cells"]
+		.get(0)
+		.filter(|cell| cell["cell_type"] == "markdown" || cell["cell_type"] == "raw")?;
+	let source = multiline_string(&cell["source"])?;
+	let (yaml, rest) = md::front_matter(&source)?;
+	let mut json = json.clone();
+	json["cells"][0]["source"] = Value::from([rest]);
+	Some((yaml, json))
+}
 
+/// Collect labels of cells and headings in a notebook.
+fn ipynb_labels(json: &Value) -> HashSet<String> {
+	let mut slugs = HashMap::new();
+	let mut labels = HashSet::new();
 	for cell in json["cells"].as_array().unwrap() {
+		if let Some(id) = cell["id"].as_str() {
+			labels.insert(format!("cell-{id}"));
+		}
+		if cell["cell_type"] == "markdown" {
+			let source = multiline_string(&cell["source"]).unwrap_or_default();
+			labels.extend(md::md_labels(&source, &mut slugs));
+		} else if let (captions, Some(label)) = output_figure(cell)
+			&& !captions.is_empty()
+		{
+			labels.insert(label);
+		}
+	}
+	labels
+}
+
+/// Convert a notebook to Typst, to be written to `output`.
+pub fn ipynb_parse(json: Value, output: &Path) -> String {
+	// https://nbformat.readthedocs.io/en/latest/format_description.html
+	let (front_matter, json) = match split_front_matter(&json) {
+		Some((yaml, json)) => (
+			serde_yaml_ng::from_str::<Value>(&yaml)
+				.inspect_err(|e| eprintln!("Failed to parse front matter: {e}"))
+				.ok(),
+			json,
+		),
+		None => (None, json),
+	};
+	LANG.set(json["metadata"]["language_info"]["name"].as_str().unwrap().into());
+	// https://ipywidgets.readthedocs.io/en/latest/embedding.html
+	let widgets = &json["metadata"]["widgets"];
+	let widgets = match &widgets["application/vnd.jupyter.widget-state+json"] {
+		Value::Null => &widgets["state"],
+		state => &state["state"],
+	};
+	WIDGETS.set(widgets.as_object().cloned().unwrap_or_default());
+	md::set_labels(ipynb_labels(&json));
+	let markdowns: Vec<_> = json["cells"]
+		.as_array()
+		.unwrap()
+		.iter()
+		.filter(|cell| cell["cell_type"] == "markdown")
+		.filter_map(|cell| multiline_string(&cell["source"]))
+		.collect();
+	md::set_definitions(markdowns.iter().map(String::as_str));
+	katex::reset_macros();
+
+	// the abstract is Markdown, converted with the state of this notebook
+	let mut typst = match front_matter.as_ref().map(front_matter_parse) {
+		Some(args) if !args.is_empty() => format!("{TEMPLATE_WITH}{args})\n\n"),
+		_ => String::from(TEMPLATE),
+	};
+
+	for (index, cell) in json["cells"].as_array().unwrap().iter().enumerate() {
+		katex::set_cell(index + 1);
 		// attachments
 		let mut attachments: HashMap<String, String> = HashMap::new();
 		if let Some(item) = cell["attachments"].as_object() {
@@ -34,11 +131,15 @@ pub fn ipynb_parse(json: Value) -> String {
 			}
 		};
 
-		// source and output
-		output += &format!(
-			"#block[\n{}]\n",
-			match cell["cell_type"].as_str().unwrap() {
-				"markdown" => md::md_to_typst(
+		// source and output, labeled with the cell id from nbformat 4.5
+		let label = cell["id"]
+			.as_str()
+			.map(|id| format!(" <cell-{id}>"))
+			.unwrap_or_default();
+		typst += &match cell["cell_type"].as_str().unwrap() {
+			"markdown" => format!(
+				"#block[\n{}]{label}\n",
+				md::md_to_typst(
 					&cell["source"]
 						.as_array()
 						.unwrap()
@@ -46,46 +147,115 @@ pub fn ipynb_parse(json: Value) -> String {
 						.map(|v| v.as_str().unwrap())
 						.join(""),
 					attachments,
+				)
+			),
+			"code" => format!(
+				"#block[\n{}]{label}\n#block[\n{}]\n",
+				code_parse(
+					&cell["source"]
+						.as_array()
+						.unwrap()
+						.iter()
+						.map(|v| v.as_str().unwrap())
+						.join(""),
+					cell["execution_count"].as_i64().unwrap_or_default(),
 				),
-				"code" => format!(
-					"{}]\n#block[\n{}",
-					code_parse(
-						&cell["source"]
-							.as_array()
-							.unwrap()
-							.iter()
-							.map(|v| v.as_str().unwrap())
-							.join(""),
-						cell["execution_count"].as_i64().unwrap_or_default(),
-					),
-					code_output_parse(&cell["outputs"]),
-				),
-				_ => "".into(),
-			},
+				code_output_parse(&cell["outputs"], output_figure(cell)),
+			),
+			_ => format!("#block[\n]{label}\n"),
+		};
+	}
+
+	if let Some(files) = BIBLIOGRAPHY.get().filter(|files| !files.is_empty()) {
+		// Typst resolves paths relative to the output file, which can be in another directory with `-o`
+		let out_dir = env::current_dir().unwrap().join(output).parent().unwrap().to_path_buf();
+		let out_dir = fs::canonicalize(&out_dir).unwrap_or(out_dir);
+		typst += &format!(
+			"#bibliography(({},))\n",
+			files
+				.iter()
+				.map(|file| format!("\"{}\"", typ::escape_string(&relative_path(file, &out_dir))))
+				.join(", ")
 		);
 	}
 
-	output
+	typst
+}
+
+/// Path relative to `base` with `/` as separator, both paths are absolute.
+fn relative_path(path: &Path, base: &Path) -> String {
+	let common = path
+		.components()
+		.zip(base.components())
+		.take_while(|(a, b)| a == b)
+		.count();
+	base.components()
+		.skip(common)
+		.map(|_| "..".to_string())
+		.chain(
+			path.components()
+				.skip(common)
+				.map(|c| c.as_os_str().to_string_lossy().into_owned()),
+		)
+		.join("/")
 }
 
 fn code_parse(code: &str, count: i64) -> String {
-	format!(
-		"#code-block(\"{}\"\n, lang: \"{}\", count: {})\n",
-		typ::escape_string(code),
-		LANG.get().unwrap(),
-		count
-	)
+	LANG.with_borrow(|lang| {
+		format!(
+			"#code-block(\"{}\"\n, lang: \"{}\", count: {})\n",
+			typ::escape_string(code),
+			lang,
+			count
+		)
+	})
+}
+
+/// Captions and label for image outputs of a code cell, from `caption`/`fig-cap` and `label` metadata, MyST-NB
+/// `mystnb.figure` metadata or Quarto `#| fig-cap:` and `#| label:` options.
+fn output_figure(cell: &Value) -> (Vec<String>, Option<String>) {
+	let metadata = &cell["metadata"];
+	let mystnb = &metadata["mystnb"]["figure"];
+	let source = multiline_string(&cell["source"]).unwrap_or_default();
+	let options: HashMap<_, _> = source
+		.lines()
+		.map_while(|line| line.strip_prefix("#|"))
+		.filter_map(|option| option.split_once(':'))
+		.map(|(key, value)| (key.trim(), Value::from(value.trim().trim_matches('"'))))
+		.collect();
+	let find = |values: &[&Value], option: &str| {
+		values
+			.iter()
+			.copied()
+			.chain(options.get(option))
+			.find(|value| !value.is_null())
+			.cloned()
+	};
+
+	let captions = match find(
+		&[&metadata["caption"], &metadata["fig-cap"], &mystnb["caption"]],
+		"fig-cap",
+	) {
+		Some(Value::Array(captions)) => captions.iter().filter_map(|c| c.as_str().map(String::from)).collect(),
+		Some(Value::String(caption)) if !caption.is_empty() => vec![caption],
+		_ => Vec::new(),
+	};
+	let label =
+		find(&[&metadata["label"], &mystnb["name"]], "label").and_then(|label| label.as_str().map(String::from));
+	(captions, label)
 }
 
-fn code_output_parse(outputs: &Value) -> String {
+fn code_output_parse(outputs: &Value, (captions, label): (Vec<String>, Option<String>)) -> String {
 	let img_path = IMG_PATH.get().unwrap();
+	let prefer_vector = PREFER_VECTOR.get().copied().unwrap_or_default();
+	let mut images = 0;
 
 	outputs
 		.as_array()
 		.unwrap()
 		.iter()
 		.map(|output| {
-			match output["output_type"].as_str().unwrap() {
+			let typst = match output["output_type"].as_str().unwrap() {
 				"stream" => format!(
 					"#result-block(\"{}\")\n",
 					output["text"]
@@ -97,28 +267,53 @@ fn code_output_parse(outputs: &Value) -> String {
 				),
 				"display_data" | "execute_result" => {
 					let data = &output["data"];
-					if let Some(img) = data["image/svg+xml"].as_array() {
-						fs::create_dir_all(img_path).unwrap();
-						let content = img.iter().map(|v| v.as_str().unwrap()).join("");
-						let file_path = format!("{}/{}.svg", img_path, md::sha1(&content));
-						let mut file = File::create(&file_path).unwrap();
-						file.write_all(content.as_bytes()).unwrap();
-						format!("#image(\"./{file_path}\")\n")
-					} else if let Some(img) = data["image/png"].as_str() {
-						// base 64 image data
-						fs::create_dir_all(img_path).unwrap();
-						let file_path = format!("{}/{}.png", img_path, md::sha1(img));
-						let mut file = File::create(&file_path).unwrap();
-						file.write_all(&STANDARD.decode(img).unwrap()).unwrap();
-						format!("#image(\"./{file_path}\")\n")
+					if let Some(typst) = mime_handler_parse(data) {
+						typst
+					} else if let Some(mime) = image_mime(data, prefer_vector) {
+						match mime {
+							"image/svg+xml" => {
+								fs::create_dir_all(img_path).unwrap();
+								let content = data[mime]
+									.as_array()
+									.unwrap()
+									.iter()
+									.map(|v| v.as_str().unwrap())
+									.join("");
+								let file_path = format!("{}/{}.svg", img_path, md::sha1(&content));
+								let mut file = File::create(&file_path).unwrap();
+								file.write_all(content.as_bytes()).unwrap();
+								format!("#image(\"./{file_path}\")\n")
+							}
+							// leave PDF to the template, with the PNG sibling as fallback
+							"application/pdf" => format!(
+								"#pdf-image(\"./{}\", fallback: {})\n",
+								write_base64(data[mime].as_str().unwrap(), "pdf"),
+								data["image/png"]
+									.as_str()
+									.map_or("none".into(), |img| format!("\"./{}\"", write_base64(img, "png")))
+							),
+							_ => format!("#image(\"./{}\")\n", write_base64(data[mime].as_str().unwrap(), "png")),
+						}
+					} else if let Some(typst) = interactive_parse(data) {
+						typst
+					} else if let Some(json) = ["application/json", "application/geo+json"]
+						.iter()
+						.map(|mime| &data[mime])
+						.find(|json| !json.is_null())
+					{
+						format!(
+							"#raw(\"{}\", lang: \"json\", block: true)\n",
+							typ::escape_string(&serde_json::to_string_pretty(json).unwrap())
+						)
+					} else if let Some(csv) = multiline_string(&data["text/csv"]) {
+						csv_to_typst(&csv)
 					} else if let Some(text) = data["text/plain"].as_array() {
 						format!(
 							"#result-block(\"{}\")\n",
 							typ::escape_string(&text.iter().map(|v| v.as_str().unwrap()).join("")),
 						)
 					} else if let Some(text) = data["text/latex"].as_array() {
-						katex::text_to_typst(&text.iter().map(|v| v.as_str().unwrap()).join("").replace("$$", "$"))
-							.unwrap()
+						katex::latex_text(&text.iter().map(|v| v.as_str().unwrap()).join("").replace("$$", "$"))
 					} else if let Some(text) = data["text/html"].as_array() {
 						md::html_to_typst(text.iter().map(|v| v.as_str().unwrap()).join("").as_str())
 					} else {
@@ -135,7 +330,405 @@ fn code_output_parse(outputs: &Value) -> String {
 						.join("\n"),
 				),
 				other => unreachable!("unhandled output type: {other}\n"),
+			};
+			// image outputs take the captions in order, the first one is labeled
+			if (typst.starts_with("#image(") || typst.starts_with("#pdf-image("))
+				&& let Some(caption) = captions.get(images)
+			{
+				images += 1;
+				format!(
+					"#figure(\n  {},\n  caption: [{}],\n){}\n",
+					&typst.trim_end()[1..],
+					typ::escape_content(caption),
+					label
+						.as_ref()
+						.filter(|_| images == 1)
+						.map(|label| format!(" <{label}>"))
+						.unwrap_or_default()
+				)
+			} else {
+				typst
 			}
 		})
 		.join("")
 }
+
+/// MIME type of the image to include from a bundle, SVG first, then PDF with `--prefer-vector` or without PNG.
+fn image_mime(data: &Value, prefer_vector: bool) -> Option<&'static str> {
+	if data["image/svg+xml"].is_array() {
+		Some("image/svg+xml")
+	} else if data["application/pdf"].is_string() && (prefer_vector || data["image/png"].is_null()) {
+		Some("application/pdf")
+	} else if data["image/png"].is_string() {
+		Some("image/png")
+	} else {
+		None
+	}
+}
+
+/// Convert a MIME bundle with the first registered handler that matches.
+fn mime_handler_parse(data: &Value) -> Option<String> {
+	// the registry is unlocked before the call
+	let (mime, handler) = MIME_HANDLERS
+		.lock()
+		.unwrap()
+		.iter()
+		.find(|(mime, _)| !data[mime].is_null())
+		.cloned()?;
+	Some(handler(&data[&mime]))
+}
+
+/// Convert front matter fields to template arguments.
+fn front_matter_parse(yaml: &Value) -> String {
+	let text = |value: &Value| match value {
+		Value::String(s) => Some(s.clone()),
+		Value::Null | Value::Array(_) | Value::Object(_) => None,
+		value => Some(value.to_string()),
+	};
+	let authors = match &yaml["authors"] {
+		Value::Null => &yaml["author"],
+		authors => authors,
+	};
+	let authors: Vec<_> = match authors {
+		Value::Array(authors) => authors
+			.iter()
+			.filter_map(|author| text(author).or_else(|| text(&author["name"])))
+			.collect(),
+		author => text(author).or_else(|| text(&author["name"])).into_iter().collect(),
+	};
+
+	[
+		text(&yaml["title"]).map(|title| format!("title: [{}]", typ::escape_content(&title))),
+		(!authors.is_empty()).then(|| {
+			format!(
+				"authors: ({},)",
+				authors
+					.iter()
+					.map(|author| format!("\"{}\"", typ::escape_string(author)))
+					.join(", ")
+			)
+		}),
+		text(&yaml["date"]).map(|date| format!("date: \"{}\"", typ::escape_string(&date))),
+		text(&yaml["abstract"]).map(|abs| {
+			format!(
+				"abstract: [{}]",
+				md::md_to_typst(&abs, HashMap::new()).trim_end_matches('\n')
+			)
+		}),
+	]
+	.into_iter()
+	.flatten()
+	.join(", ")
+}
+
+/// Write base 64 output data to the image path, returns the file path.
+fn write_base64(data: &str, extension: &str) -> String {
+	let img_path = IMG_PATH.get().unwrap();
+	fs::create_dir_all(img_path).unwrap();
+	let file_path = format!("{}/{}.{}", img_path, md::sha1(data), extension);
+	let mut file = File::create(&file_path).unwrap();
+	file.write_all(&STANDARD.decode(data).unwrap()).unwrap();
+	file_path
+}
+
+/// Summarize interactive outputs without a static image in the bundle.
+fn interactive_parse(data: &Value) -> Option<String> {
+	let title_text = |title: &Value| title.as_str().or_else(|| title["text"].as_str()).map(String::from);
+
+	let (kind, title, summary) = if let Some(plot) = data.get("application/vnd.plotly.v1+json") {
+		let traces = plot["data"].as_array().map(Vec::as_slice).unwrap_or_default();
+		(
+			"Plotly",
+			title_text(&plot["layout"]["title"]),
+			format!(
+				"{} trace(s): {}",
+				traces.len(),
+				traces
+					.iter()
+					.map(|trace| trace["type"].as_str().unwrap_or("scatter"))
+					.unique()
+					.join(", ")
+			),
+		)
+	} else if let Some((mime, spec)) = data
+		.as_object()?
+		.iter()
+		.find(|(mime, _)| mime.starts_with("application/vnd.vega"))
+	{
+		let mark = match &spec["mark"] {
+			Value::String(mark) => Some(mark.as_str()),
+			mark => mark["type"].as_str(),
+		};
+		let values = spec["data"]["values"].as_array().map(Vec::len);
+		(
+			if mime.starts_with("application/vnd.vegalite.") {
+				"Vega-Lite"
+			} else {
+				"Vega"
+			},
+			title_text(&spec["title"]),
+			[
+				mark.map(|mark| format!("mark: {mark}")),
+				values.map(|values| format!("{values} data point(s)")),
+			]
+			.into_iter()
+			.flatten()
+			.join(", "),
+		)
+	} else if let Some(view) = data.get("application/vnd.jupyter.widget-view+json") {
+		let id = view["model_id"].as_str().unwrap_or_default();
+		match WIDGETS.with_borrow(|widgets| widgets.get(id).cloned()) {
+			Some(model) => (
+				"widget",
+				model["state"]["description"]
+					.as_str()
+					.filter(|d| !d.is_empty())
+					.map(String::from),
+				match &model["state"]["value"] {
+					Value::Null => model["model_name"].as_str().unwrap_or_default().to_string(),
+					value => format!("{}, value: {}", model["model_name"].as_str().unwrap_or_default(), value),
+				},
+			),
+			None => ("widget", None, format!("model {id}, state not saved")),
+		}
+	} else {
+		return None;
+	};
+
+	Some(format!(
+		"#interactive-output(\"{}\", title: {}, summary: \"{}\")\n",
+		kind,
+		title.map_or("none".into(), |title| format!("\"{}\"", typ::escape_string(&title))),
+		typ::escape_string(&summary)
+	))
+}
+
+/// Join a multiline string, which is either a string or an array of strings in nbformat.
+fn multiline_string(value: &Value) -> Option<String> {
+	match value {
+		Value::String(s) => Some(s.clone()),
+		Value::Array(a) => Some(a.iter().map(|v| v.as_str().unwrap()).join("")),
+		_ => None,
+	}
+}
+
+/// Convert CSV to a Typst table, the first record is used as the header.
+fn csv_to_typst(csv: &str) -> String {
+	let mut records = csv_parse(csv).into_iter();
+	let Some(header) = records.next() else {
+		return "".into();
+	};
+	let records: Vec<_> = records.collect();
+	let columns = records.iter().map(Vec::len).chain([header.len()]).max().unwrap();
+	let row = |record: &[String]| {
+		record
+			.iter()
+			.map(|field| format!("[{}]", typ::escape_content(field)))
+			.chain(std::iter::repeat_n("[]".to_string(), columns - record.len()))
+			.join(", ")
+	};
+
+	format!(
+		"#table(\n  columns: {},\n  table.header(\n    {},\n  ),\n{})\n",
+		columns,
+		row(&header),
+		records.iter().map(|record| format!("  {},\n", row(record))).join("")
+	)
+}
+
+/// Split CSV into records of fields, following RFC 4180 quoting.
+fn csv_parse(csv: &str) -> Vec<Vec<String>> {
+	let mut records = Vec::new();
+	let mut record = Vec::new();
+	let mut field = String::new();
+	let mut quoted = false;
+	let mut chars = csv.chars().peekable();
+	while let Some(c) = chars.next() {
+		match c {
+			'"' if quoted => {
+				if chars.peek() == Some(&'"') {
+					chars.next();
+					field.push('"');
+				} else {
+					quoted = false;
+				}
+			}
+			'"' if field.is_empty() => quoted = true,
+			',' if !quoted => record.push(std::mem::take(&mut field)),
+			'\r' if !quoted => {}
+			'\n' if !quoted => {
+				record.push(std::mem::take(&mut field));
+				records.push(std::mem::take(&mut record));
+			}
+			_ => field.push(c),
+		}
+	}
+	if !field.is_empty() || !record.is_empty() {
+		record.push(field);
+		records.push(record);
+	}
+	records
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_interactive_parse() {
+		assert_eq!(
+			interactive_parse(&serde_json::json!({
+				"application/vnd.plotly.v1+json": {
+					"data": [{"type": "bar"}, {"type": "bar"}, {}],
+					"layout": {"title": {"text": "Sales"}},
+				},
+				"text/html": "<div></div>",
+			}))
+			.unwrap(),
+			"#interactive-output(\"Plotly\", title: \"Sales\", summary: \"3 trace(s): bar, scatter\")\n"
+		);
+		assert_eq!(
+			interactive_parse(&serde_json::json!({
+				"application/vnd.vegalite.v5+json": {"mark": {"type": "line"}, "data": {"values": [{}, {}]}},
+			}))
+			.unwrap(),
+			"#interactive-output(\"Vega-Lite\", title: none, summary: \"mark: line, 2 data point(s)\")\n"
+		);
+		assert_eq!(
+			interactive_parse(&serde_json::json!({
+				"application/vnd.jupyter.widget-view+json": {"model_id": "abc", "version_major": 2},
+			}))
+			.unwrap(),
+			"#interactive-output(\"widget\", title: none, summary: \"model abc, state not saved\")\n"
+		);
+		assert!(interactive_parse(&serde_json::json!({"text/plain": "1"})).is_none());
+	}
+
+	#[test]
+	fn test_front_matter_parse() {
+		let yaml = serde_yaml_ng::from_str::<Value>(
+			"title: A \"Report\"\nauthor:\n  - name: Ann\n  - Bob\ndate: 2024-01-02\nabstract: Some *text*.\nkernelspec: {}",
+		)
+		.unwrap();
+		assert_eq!(
+			front_matter_parse(&yaml),
+			"title: [A \\\"Report\\\"], authors: (\"Ann\", \"Bob\",), date: \"2024-01-02\", abstract: [Some #emph[text].]"
+		);
+		assert_eq!(
+			front_matter_parse(&serde_yaml_ng::from_str::<Value>("jupytext: {}").unwrap()),
+			""
+		);
+	}
+
+	#[test]
+	fn test_ipynb_parse_front_matter() {
+		let notebook = |definition: &str| {
+			serde_json::json!({
+				"metadata": {"language_info": {"name": "python"}},
+				"cells": [
+					{"cell_type": "markdown", "source": ["---\n", "abstract: See [docs].\n", "---\n"]},
+					{"cell_type": "markdown", "source": [definition]},
+				],
+			})
+		};
+		ipynb_parse(notebook("[docs]: https://first.example"), Path::new("a.typ"));
+		// the abstract uses the definitions of its own notebook
+		assert!(
+			ipynb_parse(notebook("[docs]: https://second.example"), Path::new("b.typ")).starts_with(
+				"#import \"template.typ\": *\n#show: template.with(abstract: [See #link(\"https://second.example\")[docs].])"
+			)
+		);
+	}
+
+	#[test]
+	fn test_relative_path() {
+		assert_eq!(
+			relative_path(Path::new("/a/b/refs.bib"), Path::new("/a/c/d")),
+			"../../b/refs.bib"
+		);
+		assert_eq!(relative_path(Path::new("/a/refs.bib"), Path::new("/a")), "refs.bib");
+	}
+
+	#[test]
+	fn test_ipynb_labels() {
+		let json = serde_json::json!({"cells": [
+			{"cell_type": "markdown", "id": "a", "source": "# Intro\n\nText"},
+			{"cell_type": "markdown", "source": ["## Intro"]},
+		]});
+		assert_eq!(
+			ipynb_labels(&json),
+			HashSet::from(["cell-a".into(), "intro".into(), "intro-1".into()])
+		);
+	}
+
+	#[test]
+	fn test_csv_parse() {
+		assert_eq!(
+			csv_parse("a,b\r\n\"1,2\",\"say \"\"hi\"\"\"\n3\n"),
+			vec![vec!["a", "b"], vec!["1,2", "say \"hi\""], vec!["3"]]
+		);
+	}
+
+	#[test]
+	fn test_csv_to_typst() {
+		assert_eq!(
+			csv_to_typst("name,value\nx_1,-1\ny\n"),
+			"#table(
+  columns: 2,
+  table.header(
+    [name], [value],
+  ),
+  [x\\_1], [\\-1],
+  [y], [],
+)
+"
+		);
+	}
+
+	#[test]
+	fn test_output_figure() {
+		let cell = serde_json::json!({
+			"metadata": {"mystnb": {"figure": {"caption": "Sine", "name": "fig-sine"}}},
+			"source": ["plot(x)"],
+		});
+		assert_eq!(output_figure(&cell), (vec!["Sine".into()], Some("fig-sine".into())));
+		let cell = serde_json::json!({
+			"metadata": {},
+			"source": ["#| label: fig-plots\n", "#| fig-cap:\n", "#|   - \"A\"\n", "plot(x)"],
+		});
+		assert_eq!(output_figure(&cell), (vec![], Some("fig-plots".into())));
+		let cell =
+			serde_json::json!({"metadata": {"caption": ["A", "B"]}, "source": "#| fig-cap: \"Quarto\"\nplot(x)"});
+		assert_eq!(output_figure(&cell), (vec!["A".into(), "B".into()], None));
+		let cell = serde_json::json!({"metadata": {}, "source": "#| fig-cap: \"Quarto\"\nplot(x)"});
+		assert_eq!(output_figure(&cell), (vec!["Quarto".into()], None));
+	}
+
+	#[test]
+	fn test_mime_handler_parse() {
+		register_mime_handler("application/x-test-outer", |value| {
+			// registering from a handler doesn't deadlock
+			register_mime_handler("application/x-test-inner", |value| format!("inner {value}"));
+			let inner = mime_handler_parse(&serde_json::json!({"application/x-test-inner": 1})).unwrap();
+			format!("outer {value}, {inner}")
+		});
+		assert_eq!(
+			mime_handler_parse(&serde_json::json!({"application/x-test-outer": "a", "text/plain": "b"})).unwrap(),
+			"outer \"a\", inner 1"
+		);
+		assert!(mime_handler_parse(&serde_json::json!({"text/x-test-none": "b"})).is_none());
+	}
+
+	#[test]
+	fn test_image_mime() {
+		let all = serde_json::json!({"image/svg+xml": ["<svg/>"], "application/pdf": "JVBE", "image/png": "iVBO"});
+		assert_eq!(image_mime(&all, false), Some("image/svg+xml"));
+		assert_eq!(image_mime(&all, true), Some("image/svg+xml"));
+		let raster = serde_json::json!({"application/pdf": "JVBE", "image/png": "iVBO", "text/plain": "<Figure>"});
+		assert_eq!(image_mime(&raster, false), Some("image/png"));
+		assert_eq!(image_mime(&raster, true), Some("application/pdf"));
+		let pdf = serde_json::json!({"application/pdf": "JVBE"});
+		assert_eq!(image_mime(&pdf, false), Some("application/pdf"));
+		assert_eq!(image_mime(&serde_json::json!({"text/plain": "1"}), true), None);
+	}
+}
@@ -1,42 +1,419 @@
-use std::{borrow::Cow, iter::Peekable, str::Chars};
+use std::{
+	borrow::Cow,
+	cell::{Cell, RefCell},
+	collections::{BTreeMap, HashMap},
+	iter::Peekable,
+	str::Chars,
+};
 use thiserror::Error;
 
-use crate::typ;
+use crate::{MACROS, NUMBERING, Numbering, typ};
 use itertools::Itertools;
-use regex::Regex;
+use parser::{Kind, Node};
+
+mod mhchem;
+mod parser;
 
 const BINARY_OPERATORS: &[char] = &['_', '^'];
+/// Nested macro expansions before giving up, for recursive definitions like `\def\a{\a}`
+const MACRO_DEPTH: usize = 64;
+/// Environments numbered in LaTeX, with `--numbering`
+const NUMBERED_ENVIRONMENTS: &[&str] = &["align", "alignat", "equation", "flalign", "gather", "multline"];
+/// Environments whose rows can be numbered separately
+const ROW_ENVIRONMENTS: &[&str] = &["align", "align*", "flalign", "flalign*", "gather", "gather*"];
+/// Names in the math scope of Typst 0.14, commands with these names are passed through as is
+const TYPST_NAMES: &[&str] = &[
+	"AA",
+	"Alpha",
+	"BB",
+	"Beta",
+	"CC",
+	"Chi",
+	"DD",
+	"Delta",
+	"Dif",
+	"EE",
+	"Epsilon",
+	"Eta",
+	"FF",
+	"GG",
+	"Gamma",
+	"HH",
+	"II",
+	"Im",
+	"Iota",
+	"JJ",
+	"KK",
+	"Kai",
+	"Kappa",
+	"LL",
+	"Lambda",
+	"MM",
+	"Mu",
+	"NN",
+	"Nu",
+	"OO",
+	"Omega",
+	"Omicron",
+	"PP",
+	"Phi",
+	"Pi",
+	"Pr",
+	"Psi",
+	"QQ",
+	"RR",
+	"Re",
+	"Rho",
+	"SS",
+	"Sigma",
+	"TT",
+	"Tau",
+	"Theta",
+	"UU",
+	"Upsilon",
+	"VV",
+	"WW",
+	"XX",
+	"Xi",
+	"YY",
+	"ZZ",
+	"Zeta",
+	"abs",
+	"accent",
+	"acute",
+	"alef",
+	"aleph",
+	"alpha",
+	"amp",
+	"and",
+	"angle",
+	"angstrom",
+	"approx",
+	"arccos",
+	"arcsin",
+	"arctan",
+	"arg",
+	"arrow",
+	"arrowhead",
+	"arrows",
+	"ast",
+	"at",
+	"attach",
+	"backslash",
+	"ballot",
+	"bar",
+	"bb",
+	"because",
+	"bet",
+	"beta",
+	"beth",
+	"binom",
+	"bitcoin",
+	"bold",
+	"bot",
+	"brace",
+	"bracket",
+	"breve",
+	"bullet",
+	"cal",
+	"cancel",
+	"caret",
+	"caron",
+	"cases",
+	"ceil",
+	"checkmark",
+	"chi",
+	"circle",
+	"class",
+	"co",
+	"colon",
+	"comma",
+	"complement",
+	"compose",
+	"convolve",
+	"copyright",
+	"cos",
+	"cosh",
+	"cot",
+	"coth",
+	"csc",
+	"csch",
+	"ctg",
+	"dagger",
+	"dalet",
+	"daleth",
+	"dash",
+	"deg",
+	"degree",
+	"delta",
+	"det",
+	"diaer",
+	"diameter",
+	"diamond",
+	"dif",
+	"diff",
+	"dim",
+	"display",
+	"div",
+	"divides",
+	"dollar",
+	"dot",
+	"dotless",
+	"dots",
+	"ell",
+	"ellipse",
+	"emptyset",
+	"epsilon",
+	"eq",
+	"equation",
+	"equiv",
+	"eta",
+	"euro",
+	"excl",
+	"exists",
+	"exp",
+	"fence",
+	"floor",
+	"floral",
+	"forall",
+	"forces",
+	"frac",
+	"frak",
+	"franc",
+	"gamma",
+	"gcd",
+	"gimel",
+	"gimmel",
+	"gradient",
+	"grave",
+	"gt",
+	"harpoon",
+	"harpoons",
+	"hash",
+	"hat",
+	"hexa",
+	"hom",
+	"hyph",
+	"id",
+	"im",
+	"in",
+	"inf",
+	"infinity",
+	"inline",
+	"integral",
+	"interrobang",
+	"iota",
+	"italic",
+	"join",
+	"kai",
+	"kappa",
+	"kelvin",
+	"ker",
+	"lambda",
+	"laplace",
+	"lcm",
+	"lg",
+	"lim",
+	"liminf",
+	"limits",
+	"limsup",
+	"lira",
+	"ln",
+	"log",
+	"lozenge",
+	"lr",
+	"lrm",
+	"lt",
+	"macron",
+	"maltese",
+	"mat",
+	"max",
+	"med",
+	"mid",
+	"min",
+	"minus",
+	"mod",
+	"models",
+	"mono",
+	"mu",
+	"multimap",
+	"nabla",
+	"norm",
+	"not",
+	"notes",
+	"nothing",
+	"nu",
+	"ohm",
+	"omega",
+	"omicron",
+	"oo",
+	"op",
+	"or",
+	"overbrace",
+	"overbracket",
+	"overline",
+	"overparen",
+	"overshell",
+	"parallel",
+	"paren",
+	"partial",
+	"penta",
+	"percent",
+	"permille",
+	"perp",
+	"peso",
+	"phi",
+	"pi",
+	"pilcrow",
+	"planck",
+	"plus",
+	"pound",
+	"prec",
+	"prime",
+	"primes",
+	"product",
+	"prop",
+	"psi",
+	"qed",
+	"quad",
+	"quest",
+	"quote",
+	"ratio",
+	"rect",
+	"refmark",
+	"rho",
+	"rlm",
+	"root",
+	"round",
+	"ruble",
+	"rupee",
+	"sans",
+	"script",
+	"scripts",
+	"sec",
+	"sech",
+	"sect",
+	"section",
+	"semi",
+	"serif",
+	"servicemark",
+	"shin",
+	"sigma",
+	"sin",
+	"sinc",
+	"sinh",
+	"slash",
+	"smash",
+	"space",
+	"sqrt",
+	"square",
+	"sscript",
+	"star",
+	"stretch",
+	"subset",
+	"succ",
+	"suit",
+	"sum",
+	"sup",
+	"supset",
+	"tack",
+	"tan",
+	"tanh",
+	"tau",
+	"text",
+	"tg",
+	"therefore",
+	"theta",
+	"thick",
+	"thin",
+	"tilde",
+	"times",
+	"top",
+	"tr",
+	"triangle",
+	"turtle",
+	"underbrace",
+	"underbracket",
+	"underline",
+	"underparen",
+	"undershell",
+	"union",
+	"upright",
+	"upsilon",
+	"vec",
+	"wide",
+	"without",
+	"wj",
+	"won",
+	"wreath",
+	"xi",
+	"xor",
+	"yen",
+	"zeta",
+	"zwj",
+	"zwnj",
+	"zws",
+];
+
+thread_local! {
+	/// Cell being converted counting from 1, for warnings
+	static CELL: Cell<usize> = const { Cell::new(0) };
+	/// <command without `\`, cells of each use> of unknown commands since the last [`take_unknown_commands`]
+	static UNKNOWN_COMMANDS: RefCell<BTreeMap<String, Vec<usize>>> = const { RefCell::new(BTreeMap::new()) };
+	/// <cell, error> of LaTeX kept as source since the last [`take_latex_errors`]
+	static LATEX_ERRORS: RefCell<Vec<(usize, String)>> = const { RefCell::new(Vec::new()) };
+	/// <name without `\`, macro> of user-defined macros in the notebook being converted
+	static USER_MACROS: RefCell<HashMap<String, Macro>> = RefCell::new(HashMap::new());
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+	params: usize,
+	/// Default of the first parameter, which is then optional
+	default: Option<String>,
+	body: String,
+}
 
 #[derive(Debug, Error)]
-pub enum ScannerError {
-	#[error("Unexpected end of input while parsing {context}")]
-	UnexpectedEof { context: String },
+pub enum ParseError {
+	#[error("Unexpected end of input while parsing {context} at byte {offset}")]
+	UnexpectedEof { context: String, offset: usize },
+	#[error("Unmatched `{token}` at byte {offset}")]
+	Unmatched { token: String, offset: usize },
+	/// Offsets are in the LaTeX after macro expansion, which differs from the source
+	#[error("{0} of the LaTeX with macros expanded")]
+	Expanded(Box<ParseError>),
 }
 
-impl ScannerError {
-	pub fn unexpected_eof(context: impl Into<String>) -> Self {
+impl ParseError {
+	pub fn unexpected_eof(context: impl Into<String>, offset: usize) -> Self {
 		Self::UnexpectedEof {
 			context: context.into(),
+			offset,
 		}
 	}
-}
 
-#[derive(Debug, Clone)]
-/// A simple one way scanner is enough of most KeTeX parsing
-struct Scanner<'a>(Peekable<Chars<'a>>);
-
-impl From<Scanner<'_>> for String {
-	fn from(value: Scanner) -> Self {
-		value.collect()
+	pub fn unmatched(token: impl Into<String>, offset: usize) -> Self {
+		Self::Unmatched {
+			token: token.into(),
+			offset,
+		}
 	}
-}
 
-impl From<&mut Scanner<'_>> for String {
-	fn from(value: &mut Scanner) -> Self {
-		value.collect()
+	/// Mark the offset as one in the LaTeX after macro expansion, if it differs from the source.
+	fn expanded(self, source: &str, expanded: &str) -> Self {
+		match self {
+			Self::Expanded(_) => self,
+			_ if source != expanded => Self::Expanded(Box::new(self)),
+			_ => self,
+		}
 	}
 }
 
+#[derive(Debug, Clone)]
+/// A simple one way scanner for macro expansion and text mode, math is parsed to a tree by [`parser`]
+struct Scanner<'a>(Peekable<Chars<'a>>);
+
 impl<'a> Scanner<'a> {
 	pub fn new(text: &'a str) -> Self {
 		Self(text.chars().peekable())
@@ -61,969 +438,562 @@ impl<'a> Scanner<'a> {
 		ret
 	}
 
-	/// Returns the next LaTeX parameter in the scanner.
-	pub fn next_param(&mut self) -> Result<String, ScannerError> {
-		let mut ret = String::new();
+	/// Return characters until one of the characters **not** in `chars` is found.
+	/// The ending character is consumed
+	pub fn until_chars_not(&mut self, chars: &str) -> String {
+		self.take_while(|&c| !chars.contains(c)).collect()
+	}
 
-		// trim whitespace
-		let next = self.by_ref().find(|&c| !c.is_whitespace());
+	/// Returns the next macro argument, a group without braces, a control sequence or a character.
+	pub fn next_arg(&mut self) -> Option<String> {
+		match self.by_ref().find(|c| !c.is_whitespace())? {
+			'{' => Some(self.group()),
+			'\\' => Some(match self.next_word().as_str() {
+				"" => format!("\\{}", self.next()?),
+				word => format!("\\{word}"),
+			}),
+			c => Some(c.to_string()),
+		}
+	}
 
-		// check if next character is `\`, `{`, or any other character
-		match next {
-			Some('\\') => {
-				ret.push('\\');
-				match self.next_word().as_str() {
-					"" => ret.push(self.next().ok_or_else(|| ScannerError::unexpected_eof(&mut *self))?),
-					word => ret += word,
-				}
-				// process training binary operators
-				while let Some(&c) = self.peek() {
-					if c.is_whitespace() {
-						self.next();
-					} else if BINARY_OPERATORS.contains(&c) {
-						self.next();
-						ret.push(c);
-						ret.push_str(&self.next_param()?);
-					} else {
-						break;
-					}
+	/// Returns the content of a group until the matching `}`, the opening `{` is already consumed.
+	pub fn group(&mut self) -> String {
+		let mut ret = String::new();
+		let mut depth = 0;
+		while let Some(c) = self.next() {
+			match c {
+				'\\' => {
+					ret.push(c);
+					ret.extend(self.next());
+					continue;
 				}
+				'{' => depth += 1,
+				'}' if depth == 0 => break,
+				'}' => depth -= 1,
+				_ => {}
 			}
-			Some('{') => {
-				let mut depth = 0;
-				ret.extend(self.by_ref().take_while(|&c| match c {
-					'{' => {
-						depth += 1;
-						true
-					}
-					'}' if depth == 0 => false,
-					'}' => {
-						depth -= 1;
-						true
-					}
-					_ => true,
-				}));
-			}
-			Some(c) => ret.push(c),
-			None => return Err(ScannerError::unexpected_eof(self)),
+			ret.push(c);
 		}
-		Ok(ret)
+		ret
 	}
 
-	/// Returns the next LaTeX optional parameter in the scanner
-	/// returns empty string if there's no optional parameter
-	pub fn next_param_optional(&mut self) -> String {
-		let mut ret = String::new();
+	/// Returns the content of `[...]` if the next non-whitespace character is `[`.
+	pub fn next_bracket(&mut self) -> Option<String> {
+		while self.peek()?.is_whitespace() {
+			self.next();
+		}
+		(self.peek() == Some(&'[')).then(|| self.by_ref().skip(1).take_while(|&c| c != ']').collect())
+	}
+}
 
-		// trim whitespace
-		let _ = self.by_ref().0.peeking_take_while(|&c| !c.is_whitespace());
+impl Iterator for Scanner<'_> {
+	type Item = char;
 
-		// check if next character is '\\', '{', or any other character
-		if let Some('[') = self.peek() {
-			self.next();
-			ret.extend(self.take_while(|&c| c != ']'));
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next()
+	}
+}
+
+/// Sibling nodes being converted, commands take their arguments from the following nodes.
+struct Cursor<'a, 'b> {
+	src: &'a str,
+	nodes: &'b [Node<'a>],
+	/// Offset of the command taking arguments, for errors
+	offset: usize,
+}
+
+impl<'a, 'b> Cursor<'a, 'b> {
+	fn new(src: &'a str, nodes: &'b [Node<'a>], offset: usize) -> Self {
+		Self { src, nodes, offset }
+	}
+
+	fn next(&mut self) -> Option<&'b Node<'a>> {
+		let (first, rest) = self.nodes.split_first()?;
+		self.nodes = rest;
+		Some(first)
+	}
+
+	/// Returns the next node after whitespace without advancing the cursor.
+	fn peek(&self) -> Option<&'b Node<'a>> {
+		self.nodes.iter().find(|node| !node.is_whitespace())
+	}
+
+	fn skip_whitespace(&mut self) {
+		while let Some(node) = self.nodes.first()
+			&& node.is_whitespace()
+		{
+			self.nodes = &self.nodes[1..];
 		}
-		ret
 	}
 
-	/// Return characters until one of the characters in `chars` is found.
-	/// The ending character is consumed
-	pub fn until_chars(&mut self, chars: &str) -> String {
-		self.take_while(|&c| chars.contains(c)).collect()
+	/// Returns the next argument, a group or a single node. A command takes its scripts along, like `\sum_0^n`.
+	fn next_param(&mut self) -> Result<Param<'a, 'b>, ParseError> {
+		self.skip_whitespace();
+		let nodes = self.nodes;
+		let mut len = match nodes.first() {
+			Some(_) => 1,
+			None => return Err(ParseError::unexpected_eof("argument", self.offset)),
+		};
+		if let Kind::Command(_) = nodes[0].kind {
+			while let Some(scripts) = nodes[len..].iter().position(|node| !node.is_whitespace())
+				&& matches!(nodes[len + scripts].kind, Kind::Sub(_) | Kind::Sup(_))
+			{
+				len += scripts + 1;
+			}
+		}
+		self.nodes = &nodes[len..];
+		Ok(Param {
+			src: self.src,
+			nodes: &nodes[..len],
+		})
 	}
 
-	/// Return characters until one of the characters **not** in `chars` is found.
-	/// The ending character is consumed
-	pub fn until_chars_not(&mut self, chars: &str) -> String {
-		self.take_while(|&c| !chars.contains(c)).collect()
+	/// Returns the optional argument in brackets, if any.
+	fn next_optional(&mut self) -> Option<Param<'a, 'b>> {
+		let start = self.nodes.iter().position(|node| !node.is_whitespace())?;
+		if self.nodes[start].kind != Kind::Char('[') {
+			return None;
+		}
+		let len = self.nodes[start + 1..]
+			.iter()
+			.position(|node| node.kind == Kind::Char(']'))?;
+		let param = Param {
+			src: self.src,
+			nodes: &self.nodes[start + 1..start + 1 + len],
+		};
+		self.nodes = &self.nodes[start + len + 2..];
+		Some(param)
+	}
+
+	/// Converts the argument of a script. A command in it takes its arguments from the following nodes, like
+	/// `x_\text{max}`.
+	fn script(&mut self, param: &Node<'a>) -> Result<String, ParseError> {
+		let Kind::Command(_) = param.kind else {
+			return nodes_to_typst(self.src, std::slice::from_ref(param));
+		};
+		let nodes = [std::slice::from_ref(param), self.nodes].concat();
+		let mut cursor = Cursor::new(self.src, &nodes[1..], param.span.start);
+		let typst = node_to_typst(&mut cursor, &nodes[0])?.into_owned();
+		self.nodes = &self.nodes[self.nodes.len() - cursor.nodes.len()..];
+		Ok(typst)
+	}
+
+	/// Converts the remaining nodes, for switches that affect the rest of the group.
+	fn rest(&mut self) -> Result<String, ParseError> {
+		nodes_to_typst(self.src, std::mem::take(&mut self.nodes))
 	}
 
-	/// Return characters until match the input string.
-	/// The ending string is consumed
-	pub fn until_string(&mut self, string: &str) -> String {
+	/// Returns the following characters while `f` holds, for `\char` codes.
+	fn chars_while(&mut self, f: impl Fn(char) -> bool) -> String {
 		let mut ret = String::new();
-		for c in self.by_ref() {
-			ret.push(c);
-			if ret.ends_with(string) {
-				ret.truncate(ret.len() - string.len());
-				break;
-			}
+		while let Some(Node {
+			kind: Kind::Char(c), ..
+		}) = self.nodes.first()
+			&& f(*c)
+		{
+			ret.push(*c);
+			self.nodes = &self.nodes[1..];
 		}
 		ret
 	}
 }
 
-impl Iterator for Scanner<'_> {
-	type Item = char;
+/// Argument of a command.
+struct Param<'a, 'b> {
+	src: &'a str,
+	nodes: &'b [Node<'a>],
+}
 
-	fn next(&mut self) -> Option<Self::Item> {
-		self.0.next()
+impl<'a, 'b> Param<'a, 'b> {
+	/// Source of the argument, without braces
+	fn raw(&self) -> &'a str {
+		parser::raw(self.src, self.nodes)
 	}
-}
 
-macro_rules! matrix {
-	// no alignment in matrix currently
-	($scanner:expr, $param:expr, $delim:expr) => {{
-		format!(
-			"mat(delim: {}, {})",
-			$delim,
-			matrix_to_typst($scanner.until_string(&format!("\\end{{{}}}", $param)))?
-		)
-	}};
-	($scanner:expr, $param:expr) => {{
-		format!(
-			"mat({})",
-			matrix_to_typst($scanner.until_string(&format!("\\end{{{}}}", $param)))?
-		)
-	}};
-}
+	fn to_typst(&self) -> Result<String, ParseError> {
+		nodes_to_typst(self.src, self.nodes)
+	}
 
-macro_rules! matrix_opt {
-	// no alignment in matrix currently
-	($scanner:expr, $param:expr, $delim:expr) => {{
-		$scanner.next_param_optional();
-		format!(
-			"mat(delim: {}, {})",
-			$delim,
-			matrix_to_typst($scanner.until_string(&format!("\\end{{{}}}", $param)))?
-		)
-	}};
-	($scanner:expr, $param:expr) => {{
-		$scanner.next_param_optional();
-		format!(
-			"mat({})",
-			matrix_to_typst($scanner.until_string(&format!("\\end{{{}}}", $param)))?
-		)
-	}};
+	/// Nodes of the argument, without braces
+	fn inner(&self) -> &'b [Node<'a>] {
+		match self.nodes {
+			[
+				Node {
+					kind: Kind::Group(nodes),
+					..
+				},
+			] => nodes,
+			nodes => nodes,
+		}
+	}
 }
 
 macro_rules! single {
-	($scanner:expr, $fn:expr) => {{ format!("{}({})", $fn, latex_to_typst($scanner.next_param()?.into())?) }};
-	($scanner:expr, $fn:expr, $params:expr) => {{
-		format!(
-			"{}({}, {})",
-			$fn,
-			$params,
-			latex_to_typst($scanner.next_param()?.into())?
-		)
-	}};
+	($cursor:expr, $fn:expr) => {{ format!("{}({})", $fn, $cursor.next_param()?.to_typst()?) }};
+	($cursor:expr, $fn:expr, $params:expr) => {{ format!("{}({}, {})", $fn, $params, $cursor.next_param()?.to_typst()?) }};
 }
 
 macro_rules! double {
-	($scanner:expr, $fn:expr) => {{
+	($cursor:expr, $fn:expr) => {{
 		format!(
 			"{}({}, {})",
 			$fn,
-			latex_to_typst($scanner.next_param()?.into())?,
-			latex_to_typst($scanner.next_param()?.into())?
+			$cursor.next_param()?.to_typst()?,
+			$cursor.next_param()?.to_typst()?
 		)
 	}};
-	($scanner:expr, $fn1:expr, $fn2:expr) => {{
+	($cursor:expr, $fn1:expr, $fn2:expr) => {{
 		format!(
 			"{}({}({}, {}))",
 			$fn1,
 			$fn2,
-			latex_to_typst($scanner.next_param()?.into())?,
-			latex_to_typst($scanner.next_param()?.into())?
+			$cursor.next_param()?.to_typst()?,
+			$cursor.next_param()?.to_typst()?
 		)
 	}};
 }
 
 macro_rules! accent {
-	($scanner:expr, $accent:expr) => {{
-		format!(
-			"accent({}, {})",
-			latex_to_typst($scanner.next_param()?.into())?,
-			$accent
-		)
-	}};
+	($cursor:expr, $accent:expr) => {{ format!("accent({}, {})", $cursor.next_param()?.to_typst()?, $accent) }};
 }
 
-pub fn latex_to_typst(latex: Cow<str>) -> Result<Cow<str>, ScannerError> {
-	let mut scanner = Scanner::new(&latex);
-	let mut text = String::with_capacity(latex.len());
+/// Clear macros of the previous notebook, and define the ones from `--macros`.
+pub fn reset_macros() {
+	USER_MACROS.with_borrow_mut(HashMap::clear);
+	if let Some(macros) = MACROS.get() {
+		expand_macros(macros, 0);
+	}
+}
+
+/// Record `\newcommand`, `\renewcommand`, `\providecommand`, `\def` and `\let` definitions and expand uses of
+/// them. Definitions are removed from the output and kept for later cells.
+fn expand_macros(latex: &str, depth: usize) -> String {
+	if depth > MACRO_DEPTH {
+		eprintln!("Macro expansion too deep: {latex}");
+		return latex.into();
+	}
+
+	let mut scanner = Scanner::new(latex);
+	let mut ret = String::with_capacity(latex.len());
 	while let Some(c) = scanner.next() {
-		let push: Cow<str> = match c {
-			'\\' => match scanner.next_word().as_str() {
-				// same one goes to default
-				"" => {
-					let c = scanner.next().unwrap();
-					match c {
-						'\'' | '"' | '.' | '`' | '=' | '~' | '^' => {
-							let func = match c {
-								'\'' => "acute",
-								'"' => "dot.double",
-								'.' => "dot",
-								'`' => "grave",
-								'=' => "macron",
-								'~' => "tilde",
-								'^' => "hat",
-								_ => unreachable!(),
-							};
-							single!(scanner, func).into()
-						}
-						// escape characters in Typst
-						'_' | '&' | '#' => format!("\\{c}").into(),
-						'!' => "#h(-1em/6)".into(),
-						' ' => "space".into(),
-						'(' | ')' => "".into(),
-						',' => "space.sixth".into(),
-						':' | '>' => "space.med".into(),
-						';' => "#h(5em/18)".into(),
-						'|' => "||".into(),
-						'\\' => "\\".into(),
-						_ => format!("{c}").into(),
-					}
+		if c != '\\' {
+			ret.push(c);
+			continue;
+		}
+		let word = scanner.next_word();
+		match word.as_str() {
+			// escaped character
+			"" => {
+				ret.push(c);
+				ret.extend(scanner.next());
+			}
+			"newcommand" | "renewcommand" | "providecommand" => {
+				if scanner.peek() == Some(&'*') {
+					scanner.next();
 				}
-				// A
-				"AA" => "circle(A)".into(),
-				"aa" => "circle(a)".into(),
-				"acute" => single!(scanner, "acute").into(),
-				"AE" => "Æ".into(),
-				"ae" => "æ".into(),
-				"alefsym" => "alef".into(),
-				"amalg" | "coprod" => "product.co".into(),
-				"And" => "\\&".into(),
-				"approxeq" => "approx.eq".into(),
-				"approxcolon" => "approx:".into(),
-				"approxcoloncolon" => "approx::".into(),
-				"arcctg" => "#math.op(\"arcctg\")".into(),
-				"arctg" => "#math.op(\"arctg\")".into(),
-				"argmax" => "arg max".into(),
-				"argmin" => "arg min".into(),
-				"ast" => "*".into(),
-				"asymp" => "≍".into(),
-				// B
-				"backepsilon" => "in.rev.small".into(),
-				"backprime" => "prime.rev".into(),
-				"backsim" => "tilde.rev".into(),
-				"backsimeq" => "tilde.eq.rev".into(),
-				"backslash" => "\\\\".into(),
-				"bar" => single!(scanner, "macron").into(),
-				"barwedge" => "⊼".into(),
-				"Bbb" => single!(scanner, "bb").into(),
-				"Bbbk" => "bb(k)".into(),
-				"bcancel" => single!(scanner, "cancel", "inverted: #true").into(),
-				"begin" => {
-					// Skip numbering because there's an issue for numbering equation separately in Typst
-					let param = scanner.next_param()?;
-					match param.as_str() {
-						"align" | "align*" | "aligned" | "equation" | "equation*" | "gather" | "gather*"
-						| "gathered" | "split" => format!(
-							"$${}$$",
-							latex_to_typst(scanner.until_string(&format!("\\end{{{param}}}")).into())?
-						)
-						.into(),
-						"alignat" | "alignat*" | "alignedat" => {
-							scanner.next_param()?;
-							format!(
-								"$${}$$",
-								latex_to_typst(scanner.until_string(&format!("\\end{{{param}}}")).into())?
-							)
-							.into()
-						}
-						"array" | "darray" => {
-							let _ = scanner.next_param();
-							format!(
-								"mat(delim: #none, {})",
-								matrix_to_typst(scanner.until_string(&format!("\\end{{{param}}}")))?
-							)
-							.into()
-						}
-						"Bmatrix" => matrix!(scanner, param, "\"{\"").into(),
-						"Bmatrix*" => matrix_opt!(scanner, param, "\"{\"").into(),
-						"bmatrix" => matrix!(scanner, param, "\"[\"").into(),
-						"bmatrix*" => matrix_opt!(scanner, param, "\"[\"").into(),
-						"cases" | "dcases" => format!(
-							"cases({})",
-							scanner
-								.until_string(&format!("\\end{{{param}}}"))
-								.split("\\\\")
-								.map(|s| latex_to_typst(s.into()))
-								.collect::<Result<Vec<_>, _>>()?
-								.join(",")
-						)
-						.into(),
-						"CD" => {
-							// TODO: begin{CD}
-							format!("CD({})", scanner.until_string(&format!("\\end{{{param}}}"))).into()
-						}
-						"matrix" => matrix!(scanner, param, "#none").into(),
-						"matrix*" => matrix_opt!(scanner, param, "#none").into(),
-						"pmatrix" => matrix!(scanner, param, "\"(\"").into(),
-						"pmatrix*" => matrix_opt!(scanner, param, "\"(\"").into(),
-						"rcases" => format!(
-							"cases(reverse: #true, {})",
-							scanner
-								.until_string(&format!("\\end{{{param}}}"))
-								.split("\\\\")
-								.map(|s| latex_to_typst(s.into()))
-								.collect::<Result<Vec<_>, _>>()?
-								.join(",")
-						)
-						.into(),
-						"smallmatrix" => format!(
-							"inline(mat(delim: #none, {}))",
-							matrix_to_typst(scanner.until_string(&format!("\\end{{{param}}}")))?
-						)
-						.into(),
-						"Vmatrix" => matrix!(scanner, param, "\"||\"").into(),
-						"Vmatrix*" => matrix_opt!(scanner, param, "\"||\"").into(),
-						"vmatrix" => matrix!(scanner, param, "\"|\"").into(),
-						"vmatrix*" => matrix_opt!(scanner, param, "\"|\"").into(),
-						_ => unreachable!(),
+				let Some(name) = scanner.next_arg() else { break };
+				let params = scanner.next_bracket().and_then(|n| n.trim().parse().ok()).unwrap_or(0);
+				let default = scanner.next_bracket();
+				let body = scanner.next_arg().unwrap_or_default();
+				let name = name.trim_start_matches('\\').to_string();
+				USER_MACROS.with_borrow_mut(|macros| {
+					if word != "providecommand" || !macros.contains_key(&name) {
+						macros.insert(name, Macro { params, default, body });
 					}
+				});
+			}
+			"def" => {
+				let Some(name) = scanner.next_arg() else { break };
+				// only undelimited parameters, `#1#2`
+				let params = scanner.until_chars_not("{").matches('#').count();
+				let body = scanner.group();
+				USER_MACROS.with_borrow_mut(|macros| {
+					macros.insert(
+						name.trim_start_matches('\\').into(),
+						Macro {
+							params,
+							default: None,
+							body,
+						},
+					)
+				});
+			}
+			"let" => {
+				let Some(name) = scanner.next_arg() else { break };
+				let target = match scanner.next_arg() {
+					Some(eq) if eq == "=" => scanner.next_arg(),
+					target => target,
 				}
-				"between" => "≬".into(),
-				"bigcap" => "sect.big".into(),
-				"bigcirc" => "circle.stroked.big".into(),
-				"bigcup" => "union.big".into(),
-				"bigdot" => "dot.circle.big".into(),
-				"bigoplus" => "plus.circle.big".into(),
-				"bigotimes" => "times.circle.big".into(),
-				"bigsqcup" => "union.sq.big".into(),
-				"bigstar" => "star.stroked".into(),
-				"bigtriangledown" => "triangle.stroked.b".into(),
-				"bigtriangleup" | "vartriangle" | "triangle" => "triangle.stroked.t".into(),
-				"biguplus" => "union.plus.big".into(),
-				"bigvee" => "or.big".into(),
-				"bigwedge" => "and.big".into(),
-				"binom" => double!(scanner, "binom").into(),
-				"blacklozenge" => "lozenge.filled".into(),
-				"blacksquare" => "square.filled".into(),
-				"blacktriangle" => "triangle.filled.t".into(),
-				"blacktriangledown" => "triangle.filled.b".into(),
-				"blacktriangleleft" => "triangle.filled.l".into(),
-				"blacktriangleright" => "triangle.filled.r".into(),
-				"bm" | "bold" | "boldsymbol" => single!(scanner, "bold").into(),
-				"bmod" | "pmod" => "mod".into(),
-				"bowtie" | "Join" => "⋈".into(),
-				"Box" => "square.stroked".into(),
-				"boxdot" => "dot.square".into(),
-				"boxed" => format!(
-					"#box(inset: (left: 3pt, right: 3pt), outset: (top: 3pt, bottom: 3pt), stroke: 0.5pt)[${}$]",
-					latex_to_typst(scanner.next_param()?.into())?
-				)
-				.into(),
-				"boxminus" => "minus.square".into(),
-				"boxplus" => "plus.square".into(),
-				"boxtimes" => "times.square".into(),
-				"Bra" | "bra" => format!("lr(angle.l {} |)", latex_to_typst(scanner.next_param()?.into())?).into(),
-				"Braket" | "braket" => format!(
-					"lr(angle.l {} angle.r)",
-					latex_to_typst(scanner.next_param()?.into())?.replace('|', "mid(|)")
-				)
-				.into(),
-				"breve" | "u" => single!(scanner, "breve").into(),
-				"bull" | "bullet" => "circle.filled.small".into(),
-				"Bumpeq" => "≎".into(),
-				"bumpeq" => "≏".into(),
-				// C
-				"cancel" => single!(scanner, "cancel").into(),
-				"Cap" | "doublecap" => "sect.double".into(),
-				"cap" => "sect".into(),
-				"cdot" | "cdotp" | "centerdot" | "sdot" => "dot.op".into(),
-				"cfrac" => double!(scanner, "display", "frac").into(),
-				"char" => {
-					let code = match scanner.peek().unwrap() {
-						'"' => {
-							scanner.next();
-							scanner.until_chars_not("0123456789abcdefABCDEF")
+				.unwrap_or_default();
+				USER_MACROS.with_borrow_mut(|macros| {
+					// the current meaning is copied, not the name
+					let value = target
+						.strip_prefix('\\')
+						.and_then(|target| macros.get(target).cloned())
+						.unwrap_or(Macro {
+							params: 0,
+							default: None,
+							body: target,
+						});
+					macros.insert(name.trim_start_matches('\\').into(), value);
+				});
+			}
+			_ => {
+				let Some(value) = USER_MACROS.with_borrow(|macros| macros.get(&word).cloned()) else {
+					ret.push(c);
+					ret += &word;
+					continue;
+				};
+				let args: Vec<String> = (0..value.params)
+					.map(|i| match (i, &value.default) {
+						(0, Some(default)) => scanner.next_bracket().unwrap_or_else(|| default.clone()),
+						_ => scanner.next_arg().unwrap_or_default(),
+					})
+					.collect();
+				let mut body = Scanner::new(&value.body);
+				let mut expanded = String::with_capacity(value.body.len());
+				while let Some(c) = body.next() {
+					match (c, body.peek()) {
+						('#', Some('#')) => {
+							body.next();
+							expanded.push('#');
 						}
-						'\'' => {
-							scanner.next();
-							format!(
-								"{:x}",
-								u32::from_str_radix(&scanner.until_chars_not("01234567"), 8).unwrap()
-							)
+						('#', Some(&d)) if ('1'..='9').contains(&d) => {
+							body.next();
+							let arg = args.get(d as usize - '1' as usize).map_or("", String::as_str);
+							// keep `_#1` a single script
+							if expanded.ends_with(BINARY_OPERATORS) {
+								expanded += &format!("{{{arg}}}");
+							} else {
+								expanded += arg;
+							}
 						}
-						_ => format!("{:x}", scanner.until_chars_not("0123456789").parse::<u32>().unwrap()),
-					};
-					// scanner.cursor -= 1;
-					format!("\\u{{{code}}}").into()
-				}
-				"cdots" | "dots" | "dotsb" | "dotsc" | "dotsi" | "dotsm" => "dots.h.c".into(),
-				"check" | "V" | "widecheck" => single!(scanner, "caron").into(),
-				"circ" => "compose".into(),
-				"circeq" => "≗".into(),
-				"circlearrowleft" => "arrow.ccw".into(),
-				"circlearrowright" => "arrow.cw".into(),
-				"circledast" => "ast.circle".into(),
-				"circledcirc" => "circle.nested".into(),
-				"circleddash" => "dash.circle".into(),
-				"circledR" => "®".into(),
-				"circledS" => "Ⓢ".into(),
-				"clubs" | "clubsuit" => "suit.club".into(),
-				"cnums" => "CC".into(),
-				"Colonapprox" => "::approx".into(),
-				"colonapprox" => ":approx".into(),
-				"coloncolon" => "::".into(),
-				"coloncolonapprox" => "::approx".into(),
-				"coloncolonequals" | "Coloneqq" => "::=".into(),
-				"coloncolonminus" | "Coloneq" => "\"::−\"".into(),
-				"coloncolonsim" | "Colonsim" => "::tilde.op".into(),
-				"coloneq" | "colonminus" => "\":−\"".into(),
-				"colonequals" | "coloneqq" => ":=".into(),
-				"colonsim" => ":tilde.op".into(),
-				"colorbox" => format!(
-					"#box(inset: (left: 3pt, right: 3pt), outset: (top: 3pt, bottom: 3pt), fill: {})[{}]",
-					color_to_typst(&scanner.next_param()?),
-					text_to_typst(&scanner.next_param()?)?
-				)
-				.into(),
-				"complexes" => "CC".into(),
-				"cong" => "tilde.equiv".into(),
-				"cosec" => "#math.op(\"cosec\")".into(),
-				"cotg" => "#math.op(\"cotg\")".into(),
-				"cth" => "#math.op(\"cth\")".into(),
-				"Cup" | "doublecup" => "union.double".into(),
-				"cup" => "union".into(),
-				"curlyeqprec" => "eq.prec".into(),
-				"curlyeqsucc" => "eq.succ".into(),
-				"curlyvee" => "or.curly".into(),
-				"curlywedge" => "and.curly".into(),
-				"curvearrowleft" => "arrow.ccw.half".into(),
-				"curvearrowright" => "arrow.cw.half".into(),
-				// D
-				"dag" => "dagger".into(),
-				"Dagger" | "ddag" | "ddagger" => "dagger.double".into(),
-				"daleth" => "ℸ".into(),
-				"Darr" | "dArr" | "Downarrow" => "arrow.b.double".into(),
-				"darr" | "downarrow" => "arrow.b".into(),
-				"dashleftarrow" => "arrow.l.dash".into(),
-				"dashrightarrow" => "arrow.r.dash".into(),
-				"dashv" => "tack.l".into(),
-				"dbinom" => double!(scanner, "display", "binom").into(),
-				"dbcolon" => "::".into(),
-				"ddot" => single!(scanner, "dot.double").into(),
-				"ddots" => "dots.down".into(),
-				"digaamma" => "ϝ".into(),
-				"dfrac" => double!(scanner, "display", "frac").into(),
-				"diagdown" => "╲".into(),
-				"diagup" => "╱".into(),
-				"Diamond" => "lozenge.stroked".into(),
-				"diamond" => "diamond.stroked.small".into(),
-				"diamonds" | "diamondsuit" => "♢".into(),
-				"displaystyle" => single!(scanner, "display").into(),
-				"divideontimes" => "times.div".into(),
-				"dot" => single!(scanner, "dot").into(),
-				"Doteq" | "doteqdot" => "≑".into(),
-				"doteq" => "≐".into(),
-				"dotplus" => "plus.dot".into(),
-				"dotso" | "ldots" | "mathellipsis" => "...".into(),
-				"doublebarwedge" => "⩞".into(),
-				"downdownarrows" => "arrows.bb".into(),
-				"downharpoonleft" => "harpoon.bl".into(),
-				"downharpoonright" => "harpoon.br".into(),
-				// E
-				"ell" => "cal(l)".into(),
-				"empty" | "emptyset" => "empty".into(),
-				"enspace" => "space.en".into(),
-				"epsilon" => "epsilon.alt".into(),
-				"eqcirc" => "≖".into(),
-				"Eqcolon" | "minuscoloncolon" => "\"−::\"".into(),
-				"eqcolon" | "minuscolon" => "dash.colon".into(),
-				"Eqqcolon" | "equalscoloncolon" => "\"=::\"".into(),
-				"eqqcolon" | "equalscolon" => "=:".into(),
-				"eqsim" => "eq.tilde".into(),
-				"eqslantgtr" => "⪖".into(),
-				"eqslantless" => "⪕".into(),
-				"eth" => "ð".into(),
-				"exist" => "exists".into(),
-				// F
-				"fallingdotseq" => "≒".into(),
-				"fbox" => format!(
-					"#box(inset: (left: 3pt, right: 3pt), outset: (top: 3pt, bottom: 3pt))[$upright({})$]",
-					text_to_typst(&scanner.next_param()?)?
-				)
-				.into(),
-				"fcolorbox" => format!(
-					"#box(inset: (left: 3pt, right: 3pt), outset: (top: 3pt, bottom: 3pt))(stroke: {}, fill: {})[$upright({})$]",
-					color_to_typst(&scanner.next_param()?),
-					color_to_typst(&scanner.next_param()?),
-					text_to_typst(&scanner.next_param()?)?
-				)
-				.into(),
-				"Finv" => "Ⅎ".into(),
-				"flat" => "♭".into(),
-				"frac" => double!(scanner, "frac").into(),
-				"frak" | "mathfrak" => single!(scanner, "frak").into(),
-				"frown" => "⌢".into(),
-				// G
-				"Game" => "⅁".into(),
-				"ge" | "geq" => ">=".into(),
-				"geqq" => "ge.equiv".into(),
-				"geqslant" => "gt.eq.slant".into(),
-				"gets" | "larr" | "leftarrow" => "<-".into(),
-				"gg" => ">>".into(),
-				"ggg" | "gggtr" => ">>>".into(),
-				"gnapprox" => "⪊".into(),
-				"gneq" => "⪈".into(),
-				"gneqq" => "gt.nequiv".into(),
-				"gnsim" => "gt.ntilde".into(),
-				"grave" => single!(scanner, "grave").into(),
-				"gt" => ">".into(),
-				"gtapprox" => "⪆".into(),
-				"gtreqless" => "gt.eq.lt".into(),
-				"gtreqqless" => "⪌".into(),
-				"gtrless" => "gt.lt".into(),
-				"gtrsim" => "gt.tilde".into(),
-				// H
-				"H" => single!(scanner, "acute.double").into(),
-				"Harr" | "hArr" | "Leftrightarrow" | "Lrarr" | "lrArr" => "<=>".into(),
-				"harr" | "leftrightarrow" | "lrarr" => "<->".into(),
-				"hat" | "widehat" => single!(scanner, "hat").into(),
-				"hbar" | "hslash" => "planck.reduce".into(),
-				"hbox" | "mathnormal" | "mathop" => latex_to_typst(scanner.next_param()?.into())?,
-				"hearts" | "heartsuit" => "♡".into(),
-				"hookleftarrow" => "arrow.l.hook".into(),
-				"hookrightarrow" => "arrow.r.hook".into(),
-				"hphantom" => format!(
-					"#box(height: 0pt, hide[${}$])",
-					latex_to_typst(scanner.next_param()?.into())?
-				)
-				.into(),
-				"hspace" | "mskip" => single!(scanner, "#h").into(),
-				// I
-				"i" | "imath" => "dotless.i".into(),
-				"iff" | "Longleftrightarrow" => "<==>".into(),
-				"iiint" => "integral.triple".into(),
-				"iint" => "integral.double".into(),
-				"image" => "Im".into(),
-				"impliedby" | "Longleftarrow" => "<==".into(),
-				"implies" => "==>".into(),
-				"infin" | "infty" => "infinity".into(),
-				"injlim" => "#math.op(\"inj\\u{{2009}}lim\", limits: true)".into(),
-				"int" | "intop" => "integral".into(),
-				"intercal" => "⊺".into(),
-				"isin" => "in".into(),
-				// JK
-				"j" | "jmath" => "dotless.j".into(),
-				"KaTeX" => "\"KaTeX\"".into(),
-				"Ket" | "ket" => format!("lr(| {} angle.r)", latex_to_typst(scanner.next_param()?.into())?).into(),
-				// L
-				"lang" | "langle" => "angle.l".into(),
-				"Larr" | "lArr" | "Leftarrow" => "arrow.l.double".into(),
-				"LaTeX" => "\"LaTeX\"".into(),
-				"lBrace" => "⦃".into(),
-				"lbrace" => "{".into(),
-				"lbrack" => "[".into(),
-				"lceil" => "⌈".into(),
-				"ldotp" => ".".into(),
-				"le" | "leq" => "<=".into(),
-				"leadsto" => "arrow.r.squiggly".into(),
-				"left" => format!(
-					"lr({}{}{})",
-					scanner.next_param()?,
-					latex_to_typst(scanner.until_string("\\right").into())?,
-					scanner.next_param()?,
-				)
-				.into(),
-				"lfloor" => "⌊".into(),
-				"lgroup" => "turtle.l".into(),
-				"lhd" | "vartriangleleft" => "lt.tri".into(),
-				"limits" | "nolimits" => "".into(),
-				"ll" => "<<".into(),
-				"llbracket" => "bracket.l.double".into(),
-				"llcorner" => "⌞".into(),
-				"Lleftarrow" => "arrow.l.triple".into(),
-				"lll" | "llless" => "<<<".into(),
-				"lnapprox" => "⪉".into(),
-				"lneq" => "⪇".into(),
-				"lneqq" => "lt.nequiv".into(),
-				"lnot" => "not".into(),
-				"lnsim" => "lt.ntilde".into(),
-				"longleftarrow" => "<--".into(),
-				"longleftrightarrow" => "<-->".into(),
-				"longmapsto" => "arrow.r.long.bar".into(),
-				"Longrightarrow" => "==>".into(),
-				"longrightarrow" => "-->".into(),
-				"looparrowleft" => "arrow.l.loop".into(),
-				"looparrowright" => "arrow.r.loop".into(),
-				"lor" | "vee" => "or".into(),
-				"lozenge" => "lozenge.stroked".into(),
-				"lparen" => "(".into(),
-				"lrcorner" => "⌟".into(),
-				"lq" => "quote.l.single".into(),
-				"Lsh" => "↰".into(),
-				"lt" => "<".into(),
-				"ltimes" => "times.l".into(),
-				"lVert" | "rVert" | "Vert" => "parallel".into(),
-				"lvert" | "rvert" | "vert" => "divides".into(),
-				// M
-				"mapsto" => "arrow.r.bar".into(),
-				"mathbb" => single!(scanner, "bb").into(),
-				"mathbf" => single!(scanner, "bold").into(),
-				"mathbin" => format!("#math.op(\"{}\")", typ::escape_string(&scanner.next_param()?)).into(),
-				"mathcal" => single!(scanner, "cal").into(),
-				"mathclap" => single!(scanner, "#box", "width: 0pt").into(),
-				"mathclose" => format!("#h(0pt) {}", latex_to_typst(scanner.next_param()?.into())?).into(),
-				"mathit" => single!(scanner, "italic").into(),
-				"mathopen" => format!("{} #h(0pt)", latex_to_typst(scanner.next_param()?.into())?).into(),
-				"mathring" | "r" => single!(scanner, "circle").into(),
-				"mathrm" => single!(scanner, "upright").into(),
-				"mathsf" => single!(scanner, "sans").into(),
-				"mathsterling" => "pound".into(),
-				"mathstrut" => "#hide(box(width: 0pt, \")\"))".into(),
-				"measuredangle" => "angle.arc".into(),
-				"medspace" => "space.med".into(),
-				"mho" => "ohm.inv".into(),
-				"mid" => "|".into(),
-				"middle" => single!(scanner, "mid").into(),
-				"minuso" => "⊖".into(),
-				"models" | "vDash" => "tack.r.double".into(),
-				"mp" => "minus.plus".into(),
-				// N
-				"N" | "natnums" => "NN".into(),
-				"natural" => "♮".into(),
-				"negmedspace" => "#h(-2em/9)".into(),
-				"ncong" => "tilde.equiv.not".into(),
-				"ne" | "neq" => "!=".into(),
-				"nearrow" => "arrow.tr".into(),
-				"neg" => "not".into(),
-				"negthickspace" => "#h(-5em/18)".into(),
-				"negthinspace" => "#h(-1em/6)".into(),
-				"nexist" => "exists.not".into(),
-				"ngeq" => "gt.eq.not".into(),
-				"ngtr" => "gt.not".into(),
-				"ni" | "owns" => "in.rev".into(),
-				"nLeftarrow" => "arrow.l.double.not".into(),
-				"nleftarrow" => "arrow.l.not".into(),
-				"nLeftrightarrow" => "arrow.l.r.double.not".into(),
-				"nleftrightarrow" => "arrow.l.r.not".into(),
-				"nleq" => "lt.eq.not".into(),
-				"nless" => "lt.not".into(),
-				"nmid" => "divides.not".into(),
-				"nobreakspace" => "space.nobreak".into(),
-				"notin" => "in.not".into(),
-				"notni" => "in.rev.not".into(),
-				"notparallel" => "parallel.not".into(),
-				"nprec" => "prec.not".into(),
-				"npreceq" => "prec.eq.not".into(),
-				"nRightarrow" => "arrow.r.double.not".into(),
-				"nrightarrow" => "arrow.r.not".into(),
-				"nsim" => "tilde.not".into(),
-				"nsubseteq" | "nsupseteq" => "subset.eq.not".into(),
-				"nsucc" => "succ.not".into(),
-				"nsucceq" => "succ.eq.not".into(),
-				"ntriangleleft" => "lt.tri.not".into(),
-				"ntrianglelefteq" => "lt.tri.eq.not".into(),
-				"ntriangleright" => "gt.tri.not".into(),
-				"ntrianglerighteq" => "gt.tri.eq.not".into(),
-				"nVDash" => "⊯".into(),
-				"nVdash" => "⊮".into(),
-				"nvDash" => "tack.r.double.not".into(),
-				"nvdash" => "tack.r.not".into(),
-				"nwarrow" => "arrow.tl".into(),
-				// O
-				"O" => "Ø".into(),
-				"o" => "ø".into(),
-				"odot" => "dot.circle".into(),
-				"OE" => "Œ".into(),
-				"oe" => "œ".into(),
-				"oiiint" => "integral.vol".into(),
-				"oiint" => "integral.surf".into(),
-				"oint" => "integral.cont".into(),
-				"ominus" => "minus.circle".into(),
-				"operatorname" => format!("#math.op(\"{}\")", typ::escape_string(&scanner.next_param()?)).into(),
-				"operatorname*" | "operatornamewithlimits" => format!(
-					"#math.op(\"{}\", limits: true)",
-					typ::escape_string(&scanner.next_param()?)
-				)
-				.into(),
-				"oplus" => "plus.circle".into(),
-				"origof" => "⊶".into(),
-				"oslash" => "⊘".into(),
-				"otimes" => "times.circle".into(),
-				"overbrace" => {
-					let param1 = latex_to_typst(scanner.next_param()?.into())?;
-					match scanner.peek() {
-						Some('^') => {
-							scanner.next();
-							format!(
-								"overbrace({}, {})",
-								param1,
-								latex_to_typst(scanner.next_param()?.into())?
-							)
-							.into()
-						}
-						_ => format!("overbrace({param1})").into(),
+						_ => expanded.push(c),
 					}
 				}
-				"overgroup" => accent!(scanner, "\\u{{0311}}").into(),
-				"overleftarrow" => single!(scanner, "arrow.l").into(),
-				"overleftharpoon" => accent!(scanner, "harpoon.lt").into(),
-				"overleftrightarrow" => accent!(scanner, "arrow.l.r").into(),
-				"overline" => single!(scanner, "overline").into(),
-				"overlinesegment" => accent!(scanner, "\\u{{20e9}}").into(),
-				"overrightarrow" | "vec" => single!(scanner, "arrow").into(),
-				"overrightharpoon" => accent!(scanner, "harpoon.rt").into(),
-				// P
-				"P" => "pilcrow".into(),
-				"partial" => "diff".into(),
-				"perp" => "bot".into(),
-				"phantom" => format!("#hide[${}$]", latex_to_typst(scanner.next_param()?.into())?).into(),
-				"phi" => "phi.alt".into(),
-				"pitchfork" => "⋔".into(),
-				"plim" => "#math.op(\"plim\", limits: true)".into(),
-				"plusmn" | "pm" => "plus.minus".into(),
-				"pounds" => "pound".into(),
-				"precapprox" => "prec.approx".into(),
-				"preccurlyeq" => "prec.eq".into(),
-				"preceq" => "⪯".into(),
-				"precnapprox" => "prec.napprox".into(),
-				"precneqq" => "prec.nequiv".into(),
-				"precnsim" => "prec.ntilde".into(),
-				"precsim" => "prec.tilde".into(),
-				"prime" | "rq" => "'".into(),
-				"prod" => "product".into(),
-				"projlim" => "#math.op(\"proj\\u{{2009}}lim\", limits: true)".into(),
-				"propto" | "varpropto" => "prop".into(),
-				// QR
-				"qquad" => "#h(2em)".into(),
-				"quad" => "space.quad".into(),
-				"R" => "RR".into(),
-				"raisebox" => format!(
-					"#text(baseline: -{})[{}]",
-					scanner.next_param()?,
-					latex_to_typst(scanner.next_param()?.into())?
-				)
-				.into(),
-				"rang" | "rangle" => "angle.r".into(),
-				"Rarr" | "rArr" | "Rightarrow" => "=>".into(),
-				"rarr" | "rightarrow" | "to" => "->".into(),
-				"ratio" => ":".into(),
-				"rBrace" => "⦄".into(),
-				"rbrace" => "}".into(),
-				"rbrack" => "]".into(),
-				"rceil" => "⌉".into(),
-				"Reals" | "reals" => "RR".into(),
-				"restriction" => "harpoon.tr".into(),
-				"rfloor" => "⌋".into(),
-				"rgroup" => "turtle.r".into(),
-				"rhd" | "vartriangleright" => "gt.tri".into(),
-				"rightarrowtail" => ">->".into(),
-				"rightharpoondown" => "harpoon.rb".into(),
-				"rightharpoonup" => "harpoon.rt".into(),
-				"rightleftarrows" => "arrows.rl".into(),
-				"rightleftharpoons" => "harpoons.rtlb".into(),
-				"rightrightarrows" => "arrows.rr".into(),
-				"rightsquigarrow" => "arrow.r.squiggly".into(),
-				"rightthreetimes" => "times.three.r".into(),
-				"risingdotseq" => "≓".into(),
-				"rmoustache" => "⎱".into(),
-				"rparen" => ")".into(),
-				"rrbracket" => "bracket.r.double".into(),
-				"Rrightarrow" => "arrow.r.triple".into(),
-				"Rsh" => "↱".into(),
-				"rtimes" => "times.r".into(),
-				"rule" => match scanner.next_param_optional().as_str() {
-					"" => format!(
-						"#box(fill: black, width: {}, height: {})",
-						scanner.next_param()?,
-						scanner.next_param()?
-					)
-					.into(),
-					p => format!(
-						"#box(inset: (bottom: {}), box(fill: black, width: {}, height: {}))",
-						p,
-						scanner.next_param()?,
-						scanner.next_param()?,
-					)
-					.into(),
-				},
-				// S
-				"S" | "sect" => "section".into(),
-				"searrow" => "arrow.br".into(),
-				"Set" | "set" => format!("{{{}}}", latex_to_typst(scanner.next_param()?.into())?).into(),
-				"setminus" | "smallsetminus" => "without".into(),
-				"sharp" => "♯".into(),
-				"sim" => "tilde.op".into(),
-				"simcolon" => "tilde.op:".into(),
-				"simcoloncolon" => "tilde.op::".into(),
-				"simeq" => "tilde.eq".into(),
-				"sh" => "#math.op(\"sh\")".into(),
-				"smallint" => "inline(integral)".into(),
-				"smallsmile" => "⌣".into(),
-				"sout" => single!(scanner, "cancel", "angle: #90deg").into(),
-				"spades" | "spadesuit" => "suit.spade".into(),
-				"sphericalangle" => "angle.spheric".into(),
-				"sqcap" => "sect.sq".into(),
-				"sqcup" => "union.sq".into(),
-				"square" => "square.stroked".into(),
-				"sqrt" => {
-					let p = scanner.next_param_optional();
-					match p.as_str() {
-						"" => single!(scanner, "sqrt").into(),
-						_ => format!(
-							"root({}, {})",
-							latex_to_typst(p.into())?,
-							latex_to_typst(scanner.next_param()?.into())?
-						)
-						.into(),
+				ret += &expand_macros(&expanded, depth + 1);
+			}
+		}
+	}
+	ret
+}
+
+/// Start converting a cell, `index` counts from 1.
+pub fn set_cell(index: usize) {
+	CELL.set(index);
+}
+
+/// Record a command emitted as is that Typst doesn't know.
+fn unknown_command(name: &str) {
+	UNKNOWN_COMMANDS.with_borrow_mut(|commands| commands.entry(name.to_string()).or_default().push(CELL.get()));
+}
+
+/// Unknown commands used since the last call, <command without `\`, cells of each use>.
+pub fn take_unknown_commands() -> BTreeMap<String, Vec<usize>> {
+	UNKNOWN_COMMANDS.take()
+}
+
+/// LaTeX kept as source since the last call, <cell, error>.
+pub fn take_latex_errors() -> Vec<(usize, String)> {
+	LATEX_ERRORS.take()
+}
+
+/// Convert inline math to `$...$`, or keep its source if it can't be converted.
+pub fn inline_math(latex: &str) -> String {
+	match latex_to_typst(latex.into()) {
+		Ok(typst) => format!("${typst}$"),
+		// text right after the call could be taken as its arguments
+		Err(e) => format!("{};", fallback(latex, &e, false)),
+	}
+}
+
+/// Convert a block equation, or keep its source if it can't be converted.
+pub fn block_math(latex: &str) -> String {
+	equation_to_typst(latex).unwrap_or_else(|e| format!("{}\n", fallback(latex, &e, true)))
+}
+
+/// Convert text with math in `$...$`, or keep its source if it can't be converted.
+pub fn latex_text(text: &str) -> String {
+	text_to_typst(text).unwrap_or_else(|e| format!("{}\n", fallback(text, &e, true)))
+}
+
+/// Record LaTeX that can't be converted and show its source with `latex-error` in the template.
+fn fallback(latex: &str, error: &ParseError, block: bool) -> String {
+	LATEX_ERRORS.with_borrow_mut(|errors| errors.push((CELL.get(), error.to_string())));
+	format!(
+		"#latex-error(\"{}\"{})",
+		typ::escape_string(latex.trim()),
+		if block { ", block: true" } else { "" }
+	)
+}
+
+pub fn latex_to_typst(latex: Cow<str>) -> Result<Cow<str>, ParseError> {
+	let expanded = expand_macros(&latex, 0);
+	parser::parse(&expanded)
+		.and_then(|nodes| nodes_to_typst(&expanded, &nodes))
+		.map(Cow::from)
+		.map_err(|e| e.expanded(&latex, &expanded))
+}
+
+/// Convert a block equation to Typst markup. Equations with `\label` or `\tag` are numbered, and so are numbered
+/// environments like `equation` depending on `--numbering`.
+pub fn equation_to_typst(latex: &str) -> Result<String, ParseError> {
+	let expanded = expand_macros(latex, 0);
+	expanded_equation_to_typst(&expanded).map_err(|e| e.expanded(latex, &expanded))
+}
+
+fn expanded_equation_to_typst(latex: &str) -> Result<String, ParseError> {
+	let nodes = parser::parse(latex)?;
+	let mode = NUMBERING.get().copied().unwrap_or_default();
+	let numbered = |name: &str| mode != Numbering::Labeled && NUMBERED_ENVIRONMENTS.contains(&name);
+	if let Some((name, rows)) = equation_rows(&nodes) {
+		let numbers: Vec<_> = rows.iter().map(|row| EquationNumber::collect(latex, row)).collect();
+		if (mode == Numbering::Row && numbered(name)) || numbers.iter().filter(|number| number.is_set()).count() > 1 {
+			// Typst numbers whole equations, so each row becomes one and `&` no longer aligns rows with each other
+			return rows
+				.iter()
+				.zip(numbers)
+				.map(|(row, number)| Ok(number.equation(&nodes_to_typst(latex, row)?, numbered(name))))
+				.collect();
+		}
+	}
+	let number = EquationNumber::collect(latex, &nodes);
+	if let Some(Node {
+		kind: Kind::Environment { name, body },
+		span,
+	}) = single_environment(&nodes)
+		&& let Some(display) = display_to_typst(latex, name, body, span.start)
+	{
+		return Ok(number.equation(&display?, numbered(name)));
+	}
+	Ok(number.equation(&nodes_to_typst(latex, &nodes)?, false))
+}
+
+/// Labels of a block equation, for links to it.
+pub fn equation_labels(latex: &str) -> Vec<String> {
+	let Ok(nodes) = parser::parse(latex) else {
+		return Vec::new();
+	};
+	let rows = equation_rows(&nodes).map_or_else(|| vec![nodes.as_slice()], |(_, rows)| rows);
+	rows.into_iter()
+		.filter_map(|row| EquationNumber::collect(latex, row).label)
+		.map(String::from)
+		.collect()
+}
+
+/// The only node besides whitespace and comments.
+fn single_environment<'a, 'b>(nodes: &'b [Node<'a>]) -> Option<&'b Node<'a>> {
+	let mut content = nodes
+		.iter()
+		.filter(|node| !node.is_whitespace() && !matches!(node.kind, Kind::Comment(_)));
+	match (content.next(), content.next()) {
+		(Some(node), None) => Some(node),
+		_ => None,
+	}
+}
+
+/// Name and non-empty rows of an equation that is a single `align` or `gather`, whose rows can be numbered
+/// separately.
+fn equation_rows<'a, 'b>(nodes: &'b [Node<'a>]) -> Option<(&'a str, Vec<&'b [Node<'a>]>)> {
+	match single_environment(nodes)?.kind {
+		Kind::Environment { name, ref body } if ROW_ENVIRONMENTS.contains(&name) => Some((
+			name,
+			matrix_rows(body)
+				.into_iter()
+				.filter(|row| row.iter().any(|node| !node.is_whitespace()))
+				.collect(),
+		)),
+		_ => None,
+	}
+}
+
+/// `\label`, `\tag` and `\notag` of a block equation or a row of it.
+#[derive(Debug, Default)]
+struct EquationNumber<'a> {
+	label: Option<&'a str>,
+	/// Tag and whether it is starred, shown without parentheses
+	tag: Option<(&'a str, bool)>,
+	notag: bool,
+}
+
+impl<'a> EquationNumber<'a> {
+	/// Collect from nodes and environments in them, the first label and tag are taken.
+	fn collect(src: &'a str, nodes: &[Node<'a>]) -> Self {
+		let mut number = Self::default();
+		number.grep(src, nodes);
+		number
+	}
+
+	fn grep(&mut self, src: &'a str, nodes: &[Node<'a>]) {
+		let mut cursor = Cursor::new(src, nodes, 0);
+		while let Some(node) = cursor.next() {
+			match &node.kind {
+				Kind::Command("label") => {
+					if let Ok(label) = cursor.next_param() {
+						self.label.get_or_insert(label.raw().trim());
 					}
 				}
-				"sqsubset" => "subset.sq".into(),
-				"sqsubseteq" => "subset.eq.sq".into(),
-				"sqsupset" => "superset.sq".into(),
-				"sqsupseteq" => "superset.eq.sq".into(),
-				"ss" => "ß".into(),
-				"star" => "star.op".into(),
-				"sub" => "subset".into(),
-				"sube" | "subseteq" => "subset.eq".into(),
-				"Subset" => "subset.double".into(),
-				"subseteqq" => "⫅".into(),
-				"subsetneq" | "varsubsetneq" => "subset.neq".into(),
-				"subsetneqq" | "varsubsetneqq" => "⫋".into(),
-				"succapprox" => "succ.approx".into(),
-				"succcurlyeq" => "succ.eq".into(),
-				"succeq" => "⪰".into(),
-				"succnapprox" => "succ.napprox".into(),
-				"succneqq" => "succ.nequiv".into(),
-				"succnsim" => "succ.ntilde".into(),
-				"supe" | "supseteq" => "supset.eq".into(),
-				"Supset" => "superset.double".into(),
-				"supseteqq" => "⫆".into(),
-				"supsetneq" | "varsupsetneq" => "superset.neq".into(),
-				"supsetneqq" | "varsupsetneqq" => "⫌".into(),
-				"surd" => "√".into(),
-				"swarrow" => "arrow.bl".into(),
-				// T
-				"tbinom" => double!(scanner, "inline", "binom").into(),
-				"TeX" => "\"TeX\"".into(),
-				"text" | "textmd" | "textnormal" | "textrm" | "textup" => {
-					format!("#[{}]", text_to_typst(&scanner.next_param()?)?).into()
-				}
-				"textbf" => format!("bold(#[{}])", text_to_typst(&scanner.next_param()?)?).into(),
-				"textcolor" => format!(
-					"#text(fill: {})[{}]",
-					color_to_typst(&scanner.next_param()?),
-					text_to_typst(&scanner.next_param()?)?
-				)
-				.into(),
-				"textit" => format!("italic(#[{}])", text_to_typst(&scanner.next_param()?)?).into(),
-				"textsf" => format!("sans(#[{}])", text_to_typst(&scanner.next_param()?)?).into(),
-				"textstyle" => format!("inline({})", latex_to_typst(scanner.next_param()?.into())?).into(),
-				"texttt" => format!("mono(#[{}])", text_to_typst(&scanner.next_param()?)?).into(),
-				"tfrac" => double!(scanner, "inline", "frac").into(),
-				"th" => "#math.op(\"th\")".into(),
-				"thetasym" => "theta.alt".into(),
-				"thickapprox" => "bold(approx)".into(),
-				"thicksim" => "bold(tilde)".into(),
-				"thickspace" => "#h(5em/18)".into(),
-				"thinspace" => "space.sixth".into(),
-				"tilde" | "widetilde" => single!(scanner, "tilde").into(),
-				"triangledown" => "triangle.stroked.b".into(),
-				"triangleleft" => "triangle.stroked.l".into(),
-				"trianglelefteq" => "lt.tri.eq".into(),
-				"triangleq" => "eq.delta".into(),
-				"triangleright" => "triangle.stroked.r".into(),
-				"trianglerighteq" => "gt.tri.eq".into(),
-				"twoheadleftarrow" => "<<-".into(),
-				"twoheadrightarrow" => "->>".into(),
-				// U
-				"Uarr" | "uArr" | "Uparrow" => "arrow.t.double".into(),
-				"uarr" | "uparrow" => "arrow.t".into(),
-				"ulcorner" => "⌜".into(),
-				"underbar" | "underline" => single!(scanner, "underline").into(),
-				"underbrace" => {
-					let param1 = latex_to_typst(scanner.next_param()?.into())?;
-					match scanner.peek() {
-						Some('_') => {
-							scanner.next();
-							format!(
-								"underbrace({}, {})",
-								param1,
-								latex_to_typst(scanner.next_param()?.into())?
-							)
-							.into()
-						}
-						_ => format!("underbrace({param1})").into(),
+				Kind::Command("tag") => {
+					let starred = cursor.nodes.first().is_some_and(|node| node.kind == Kind::Char('*'));
+					if starred {
+						cursor.next();
+					}
+					if let Ok(tag) = cursor.next_param() {
+						self.tag.get_or_insert((tag.raw().trim(), starred));
 					}
 				}
-				"undergroup" => accent!(scanner, "\\u{{032e}}").into(),
-				"underleftrightarrow" => accent!(scanner, "\\u{{034d}}").into(),
-				"unlhd" => "lt.tri.eq".into(),
-				"unrhd" => "gt.tri.eq".into(),
-				"Updownarrow" => "arrow.t.b.double".into(),
-				"updownarrow" => "arrow.t.b".into(),
-				"upharpoonleft" => "harpoon.tl".into(),
-				"upharpoonright" => "harpoon.tr".into(),
-				"uplus" => "union.plus".into(),
-				"upuparrows" => "arrows.tt".into(),
-				"urcorner" => "⌝".into(),
-				// V
-				"varDelta" => "italic(Delta)".into(),
-				"varepsilon" => "italic(epsilon)".into(),
-				"varGamma" => "italic(Gamma)".into(),
-				"varkappa" => "italic(kappa)".into(),
-				"varnothing" => "italic(nothing)".into(),
-				"varOmega" => "italic(Omega)".into(),
-				"varPhi" => "italic(Phi)".into(),
-				"varphi" => "italic(phi)".into(),
-				"varPi" => "italic(Pi)".into(),
-				"varpi" => "italic(pi.alt)".into(),
-				"varPsi" => "italic(Psi)".into(),
-				"varrho" => "italic(rho.alt)".into(),
-				"varSigma" => "italic(Sigma)".into(),
-				"varsigma" => "italic(sigma.alt)".into(),
-				"varTheta" => "italic(Theta)".into(),
-				"vartheta" => "italic(theta.alt)".into(),
-				"varUpsilon" => "italic(Upsilon)".into(),
-				"varXi" => "italic(Xi)".into(),
-				"vcentcolon" => ":".into(),
-				"Vdash" => "⊩".into(),
-				"vdash" => "tack.r".into(),
-				"vdots" => "dots.v".into(),
-				"veebar" => "⊻".into(),
-				"vphantom" => format!(
-					"#box(width: 0pt, hide[${}$])",
-					latex_to_typst(scanner.next_param()?.into())?
-				)
-				.into(),
-				"Vvdash" => "⊪".into(),
-				// W
-				"wedge" | "land" => "and".into(),
-				"weierp" | "wp" => "℘".into(),
-				"wr" => "wreath".into(),
-				// X
-				"xcancel" => single!(scanner, "cancel", "cross: #true").into(),
-				"xhookleftarrow" => single!(scanner, "xarrow", "sym: arrow.l.hook").into(),
-				"xhookrightarrow" => single!(scanner, "xarrow", "sym: arrow.r.hook").into(),
-				"xLeftarrow" => single!(scanner, "xarrow", "sym: arrow.l.double").into(),
-				"xleftarrow" => single!(scanner, "xarrow", "sym: arrow.l").into(),
-				"xleftharpoondown" => single!(scanner, "xarrow", "sym: harpoon.lb").into(),
-				"xleftharpoonup" => single!(scanner, "xarrow", "sym: harpoon.lt").into(),
-				"xLeftrightarrow" => single!(scanner, "xarrow", "sym: arrow.l.r.double").into(),
-				"xleftrightarrow" => single!(scanner, "xarrow", "sym: arrow.l.r").into(),
-				"xleftrightharpoons" => single!(scanner, "xarrow", "sym: harpoons.ltrb").into(),
-				"xlongequal" => single!(scanner, "xarrow", "sym: equal").into(),
-				"xmapsto" => single!(scanner, "xarrow", "sym: arrow.r.bar").into(),
-				"xRightarrow" => single!(scanner, "xarrow", "sym: arrow.r.double").into(),
-				"xrightarrow" => single!(scanner, "xarrow", "sym: arrow.r").into(),
-				"xrightharpoondown" => single!(scanner, "xarrow", "sym: harpoon.rb").into(),
-				"xrightharpoonup" => single!(scanner, "xarrow", "sym: harpoon.rt").into(),
-				"xrightleftharpoons" => single!(scanner, "xarrow", "sym: harpoons.rtlb").into(),
-				"xtwoheadleftarrow" => single!(scanner, "xarrow", "sym: arrow.l.twohead").into(),
-				"xtwoheadrightarrow" => single!(scanner, "xarrow", "sym: arrow.r.twohead").into(),
-				// YZ
-				"Z" => "ZZ".into(),
-				word => Cow::Owned(word.to_string()),
-			},
-			_ if BINARY_OPERATORS.contains(&c) => match scanner.peek() {
-				Some('{') => format!("{}({})", c, latex_to_typst(scanner.next_param()?.into())?).into(),
-				Some(&next) => {
-					scanner.next();
-					format!("{c}{next}").into()
-				}
-				_ => unreachable!(),
-			},
-			'%' => format!("//{}\n", scanner.until_chars("\n")).into(),
-			'~' => "space.nobreak".into(),
-			'/' | '"' => format!("\\{c}").into(),
-			'{' | '}' => "".into(),
-			_ => c.to_string().into(),
+				Kind::Command("nonumber" | "notag") => self.notag = true,
+				Kind::Environment { body, .. } => self.grep(src, body),
+				_ => {}
+			}
+		}
+	}
+
+	fn is_set(&self) -> bool {
+		self.label.is_some() || self.tag.is_some()
+	}
+
+	/// Block equation markup, `numbered` if it is in a numbered environment.
+	fn equation(&self, body: &str, numbered: bool) -> String {
+		let body = body.trim();
+		let label = match self.label {
+			Some(label) if typ::is_label_name(label) => format!(" <{label}>"),
+			Some(label) => {
+				eprintln!("Equation label can't be used in Typst: {label}");
+				String::new()
+			}
+			None => String::new(),
 		};
+		match self.tag {
+			// the tag replaces the number, which is then not counted
+			Some((tag, starred)) => format!(
+				"#math.equation(block: true, numbering: _ => \"{}\", $ {body} $){label}\n\
+				 #counter(math.equation).update(n => n - 1)\n",
+				typ::escape_string(&if starred { tag.to_string() } else { format!("({tag})") })
+			),
+			None if self.label.is_some() || (numbered && !self.notag) => {
+				format!("#math.equation(block: true, numbering: \"(1)\", $ {body} $){label}\n")
+			}
+			None => format!("$ {body} $\n"),
+		}
+	}
+}
+
+/// Convert sibling nodes, `src` is the LaTeX they are parsed from.
+fn nodes_to_typst(src: &str, nodes: &[Node]) -> Result<String, ParseError> {
+	if let Some(infix) = nodes.iter().position(|node| {
+		matches!(
+			node.kind,
+			Kind::Command("above" | "atop" | "brace" | "brack" | "choose" | "over")
+		)
+	}) {
+		return infix_to_typst(src, nodes, infix);
+	}
+
+	let mut cursor = Cursor::new(src, nodes, 0);
+	let mut text = String::with_capacity(parser::raw(src, nodes).len());
+	while let Some(node) = cursor.next() {
+		let push = node_to_typst(&mut cursor, node)?;
 		// Insert space if current and next character is an alphabetic character
 		if let Some(first) = push.chars().next()
 			&& let Some(prev) = text.chars().last()
@@ -1035,7 +1005,920 @@ pub fn latex_to_typst(latex: Cow<str>) -> Result<Cow<str>, ScannerError> {
 		text += &push;
 	}
 
-	Ok(text.into())
+	Ok(text)
+}
+
+/// Convert a node, commands take their arguments from the cursor.
+fn node_to_typst<'a, 'b>(cursor: &mut Cursor<'a, 'b>, node: &'b Node<'a>) -> Result<Cow<'b, str>, ParseError> {
+	let src = cursor.src;
+	cursor.offset = node.span.start;
+	Ok(match &node.kind {
+		Kind::Command(name) => match *name {
+			// control symbols
+			"'" | "\"" | "." | "`" | "=" | "~" | "^" => {
+				let func = match *name {
+					"'" => "acute",
+					"\"" => "dot.double",
+					"." => "dot",
+					"`" => "grave",
+					"=" => "macron",
+					"~" => "tilde",
+					"^" => "hat",
+					_ => unreachable!(),
+				};
+				single!(cursor, func).into()
+			}
+			// escape characters in Typst
+			"_" | "&" | "#" => format!("\\{name}").into(),
+			"%" | "{" | "}" => name.to_string().into(),
+			"!" => "#h(-1em/6)".into(),
+			" " => "space".into(),
+			"(" | ")" => "".into(),
+			"," => "space.sixth".into(),
+			":" | ">" => "space.med".into(),
+			";" => "#h(5em/18)".into(),
+			"|" => "||".into(),
+			"\\" => {
+				// spacing like `\\[2pt]` is left to Typst
+				cursor.next_optional();
+				// `\` followed by a character escapes it
+				match cursor.nodes.first() {
+					Some(node) if !node.is_whitespace() => "\\ ".into(),
+					_ => "\\".into(),
+				}
+			}
+			// style switches, applied to the rest of the group
+			"bf" => format!("bold({})", cursor.rest()?).into(),
+			"cal" => format!("cal({})", cursor.rest()?).into(),
+			"color" => format!(
+				"#text(fill: {})[${}$]",
+				color_to_typst(cursor.next_param()?.raw()),
+				cursor.rest()?
+			)
+			.into(),
+			"it" => format!("italic({})", cursor.rest()?).into(),
+			"rm" => format!("upright({})", cursor.rest()?).into(),
+			"scriptscriptstyle" => format!("sscript({})", cursor.rest()?).into(),
+			"scriptstyle" => format!("script({})", cursor.rest()?).into(),
+			"sf" => format!("sans({})", cursor.rest()?).into(),
+			"tt" => format!("mono({})", cursor.rest()?).into(),
+			size @ ("tiny" | "sixptsize" | "scriptsize" | "footnotesize" | "small" | "normalsize" | "large"
+			| "Large" | "LARGE" | "huge" | "Huge") => {
+				// relative to the normal size as in KaTeX
+				let em = match size {
+					"tiny" => 0.5,
+					"sixptsize" => 0.6,
+					"scriptsize" => 0.7,
+					"footnotesize" => 0.8,
+					"small" => 0.9,
+					"normalsize" => 1.0,
+					"large" => 1.2,
+					"Large" => 1.44,
+					"LARGE" => 1.728,
+					"huge" => 2.074,
+					_ => 2.488,
+				};
+				format!("#text(size: {em}em)[${}$]", cursor.rest()?).into()
+			}
+			// A
+			"AA" => "circle(A)".into(),
+			"aa" => "circle(a)".into(),
+			"acute" => single!(cursor, "acute").into(),
+			"AE" => "Æ".into(),
+			"ae" => "æ".into(),
+			"alefsym" => "alef".into(),
+			"amalg" | "coprod" => "product.co".into(),
+			"And" => "\\&".into(),
+			"approxeq" => "approx.eq".into(),
+			"approxcolon" => "approx:".into(),
+			"approxcoloncolon" => "approx::".into(),
+			"arcctg" => "#math.op(\"arcctg\")".into(),
+			"arctg" => "#math.op(\"arctg\")".into(),
+			"argmax" => "arg max".into(),
+			"argmin" => "arg min".into(),
+			"ast" => "*".into(),
+			"asymp" => "≍".into(),
+			// B
+			"backepsilon" => "in.rev.small".into(),
+			"backprime" => "prime.rev".into(),
+			"backsim" => "tilde.rev".into(),
+			"backsimeq" => "tilde.eq.rev".into(),
+			"backslash" => "\\\\".into(),
+			"bar" => single!(cursor, "macron").into(),
+			"barwedge" => "⊼".into(),
+			"Bbb" => single!(cursor, "bb").into(),
+			"Bbbk" => "bb(k)".into(),
+			"bcancel" => single!(cursor, "cancel", "inverted: #true").into(),
+			"between" => "≬".into(),
+			"bigcap" => "sect.big".into(),
+			"bigcirc" => "circle.stroked.big".into(),
+			"bigcup" => "union.big".into(),
+			"bigdot" => "dot.circle.big".into(),
+			"bigoplus" => "plus.circle.big".into(),
+			"bigotimes" => "times.circle.big".into(),
+			"bigsqcup" => "union.sq.big".into(),
+			"bigstar" => "star.stroked".into(),
+			"bigtriangledown" => "triangle.stroked.b".into(),
+			"bigtriangleup" | "vartriangle" | "triangle" => "triangle.stroked.t".into(),
+			"biguplus" => "union.plus.big".into(),
+			"bigvee" => "or.big".into(),
+			"bigwedge" => "and.big".into(),
+			"binom" => double!(cursor, "binom").into(),
+			"blacklozenge" => "lozenge.filled".into(),
+			"blacksquare" => "square.filled".into(),
+			"blacktriangle" => "triangle.filled.t".into(),
+			"blacktriangledown" => "triangle.filled.b".into(),
+			"blacktriangleleft" => "triangle.filled.l".into(),
+			"blacktriangleright" => "triangle.filled.r".into(),
+			"bm" | "bold" | "boldsymbol" => single!(cursor, "bold").into(),
+			"bmod" | "pmod" => "mod".into(),
+			"bowtie" | "Join" => "⋈".into(),
+			"Box" => "square.stroked".into(),
+			"boxdot" => "dot.square".into(),
+			"boxed" => format!(
+				"#box(inset: (left: 3pt, right: 3pt), outset: (top: 3pt, bottom: 3pt), stroke: 0.5pt)[${}$]",
+				cursor.next_param()?.to_typst()?
+			)
+			.into(),
+			"boxminus" => "minus.square".into(),
+			"boxplus" => "plus.square".into(),
+			"boxtimes" => "times.square".into(),
+			"Bra" | "bra" => format!("lr(angle.l {} |)", cursor.next_param()?.to_typst()?).into(),
+			"Braket" | "braket" => format!(
+				"lr(angle.l {} angle.r)",
+				cursor.next_param()?.to_typst()?.replace('|', "mid(|)")
+			)
+			.into(),
+			"breve" | "u" => single!(cursor, "breve").into(),
+			"bull" | "bullet" => "circle.filled.small".into(),
+			"Bumpeq" => "≎".into(),
+			"bumpeq" => "≏".into(),
+			// C
+			"cancel" => single!(cursor, "cancel").into(),
+			"Cap" | "doublecap" => "sect.double".into(),
+			"cap" => "sect".into(),
+			"cdot" | "cdotp" | "centerdot" | "sdot" => "dot.op".into(),
+			"ce" => mhchem::ce_to_typst(cursor.next_param()?.raw())?.into(),
+			"cfrac" => double!(cursor, "display", "frac").into(),
+			"char" => {
+				let code = match cursor.nodes.first().map(|node| &node.kind) {
+					Some(Kind::Char('"')) => {
+						cursor.next();
+						Some(cursor.chars_while(|c| c.is_ascii_hexdigit()))
+					}
+					Some(Kind::Char('\'')) => {
+						cursor.next();
+						u32::from_str_radix(&cursor.chars_while(|c| c.is_digit(8)), 8)
+							.ok()
+							.map(|code| format!("{code:x}"))
+					}
+					_ => cursor
+						.chars_while(|c| c.is_ascii_digit())
+						.parse::<u32>()
+						.ok()
+						.map(|code| format!("{code:x}")),
+				}
+				.filter(|code| !code.is_empty())
+				.ok_or_else(|| ParseError::unexpected_eof("character code", cursor.offset))?;
+				format!("\\u{{{code}}}").into()
+			}
+			"cdots" | "dots" | "dotsb" | "dotsc" | "dotsi" | "dotsm" => "dots.h.c".into(),
+			"check" | "V" | "widecheck" => single!(cursor, "caron").into(),
+			"circ" => "compose".into(),
+			"circeq" => "≗".into(),
+			"circlearrowleft" => "arrow.ccw".into(),
+			"circlearrowright" => "arrow.cw".into(),
+			"circledast" => "ast.circle".into(),
+			"circledcirc" => "circle.nested".into(),
+			"circleddash" => "dash.circle".into(),
+			"circledR" => "®".into(),
+			"circledS" => "Ⓢ".into(),
+			"clubs" | "clubsuit" => "suit.club".into(),
+			"cnums" => "CC".into(),
+			"Colonapprox" => "::approx".into(),
+			"colonapprox" => ":approx".into(),
+			"coloncolon" => "::".into(),
+			"coloncolonapprox" => "::approx".into(),
+			"coloncolonequals" | "Coloneqq" => "::=".into(),
+			"coloncolonminus" | "Coloneq" => "\"::−\"".into(),
+			"coloncolonsim" | "Colonsim" => "::tilde.op".into(),
+			"coloneq" | "colonminus" => "\":−\"".into(),
+			"colonequals" | "coloneqq" => ":=".into(),
+			"colonsim" => ":tilde.op".into(),
+			"colorbox" => format!(
+				"#box(inset: (left: 3pt, right: 3pt), outset: (top: 3pt, bottom: 3pt), fill: {})[{}]",
+				color_to_typst(cursor.next_param()?.raw()),
+				text_to_typst(cursor.next_param()?.raw())?
+			)
+			.into(),
+			"complexes" => "CC".into(),
+			"cong" => "tilde.equiv".into(),
+			"cosec" => "#math.op(\"cosec\")".into(),
+			"cotg" => "#math.op(\"cotg\")".into(),
+			"cth" => "#math.op(\"cth\")".into(),
+			"Cup" | "doublecup" => "union.double".into(),
+			"cup" => "union".into(),
+			"curlyeqprec" => "eq.prec".into(),
+			"curlyeqsucc" => "eq.succ".into(),
+			"curlyvee" => "or.curly".into(),
+			"curlywedge" => "and.curly".into(),
+			"curvearrowleft" => "arrow.ccw.half".into(),
+			"curvearrowright" => "arrow.cw.half".into(),
+			// D
+			"dag" => "dagger".into(),
+			"Dagger" | "ddag" | "ddagger" => "dagger.double".into(),
+			"daleth" => "ℸ".into(),
+			"Darr" | "dArr" | "Downarrow" => "arrow.b.double".into(),
+			"darr" | "downarrow" => "arrow.b".into(),
+			"dashleftarrow" => "arrow.l.dash".into(),
+			"dashrightarrow" => "arrow.r.dash".into(),
+			"dashv" => "tack.l".into(),
+			"dbinom" => double!(cursor, "display", "binom").into(),
+			"dbcolon" => "::".into(),
+			"ddot" => single!(cursor, "dot.double").into(),
+			"ddots" => "dots.down".into(),
+			"digaamma" => "ϝ".into(),
+			"dfrac" => double!(cursor, "display", "frac").into(),
+			"diagdown" => "╲".into(),
+			"diagup" => "╱".into(),
+			"Diamond" => "lozenge.stroked".into(),
+			"diamond" => "diamond.stroked.small".into(),
+			"diamonds" | "diamondsuit" => "♢".into(),
+			"displaystyle" => format!("display({})", cursor.rest()?).into(),
+			"divideontimes" => "times.div".into(),
+			"dot" => single!(cursor, "dot").into(),
+			"Doteq" | "doteqdot" => "≑".into(),
+			"doteq" => "≐".into(),
+			"dotplus" => "plus.dot".into(),
+			"dotso" | "ldots" | "mathellipsis" => "...".into(),
+			"doublebarwedge" => "⩞".into(),
+			"downdownarrows" => "arrows.bb".into(),
+			"downharpoonleft" => "harpoon.bl".into(),
+			"downharpoonright" => "harpoon.br".into(),
+			// E
+			"ell" => "cal(l)".into(),
+			"empty" | "emptyset" => "empty".into(),
+			"enspace" => "space.en".into(),
+			"epsilon" => "epsilon.alt".into(),
+			"eqcirc" => "≖".into(),
+			"Eqcolon" | "minuscoloncolon" => "\"−::\"".into(),
+			"eqcolon" | "minuscolon" => "dash.colon".into(),
+			"Eqqcolon" | "equalscoloncolon" => "\"=::\"".into(),
+			"eqqcolon" | "equalscolon" => "=:".into(),
+			"eqref" | "ref" => format!("#ref({})", typ::label(cursor.next_param()?.raw().trim())).into(),
+			"eqsim" => "eq.tilde".into(),
+			"eqslantgtr" => "⪖".into(),
+			"eqslantless" => "⪕".into(),
+			"eth" => "ð".into(),
+			"exist" => "exists".into(),
+			// F
+			"fallingdotseq" => "≒".into(),
+			"fbox" => format!(
+				"#box(inset: (left: 3pt, right: 3pt), outset: (top: 3pt, bottom: 3pt))[$upright({})$]",
+				text_to_typst(cursor.next_param()?.raw())?
+			)
+			.into(),
+			"fcolorbox" => format!(
+				"#box(inset: (left: 3pt, right: 3pt), outset: (top: 3pt, bottom: 3pt))(stroke: {}, fill: {})[$upright({})$]",
+				color_to_typst(cursor.next_param()?.raw()),
+				color_to_typst(cursor.next_param()?.raw()),
+				text_to_typst(cursor.next_param()?.raw())?
+			)
+			.into(),
+			"Finv" => "Ⅎ".into(),
+			"flat" => "♭".into(),
+			"frac" => double!(cursor, "frac").into(),
+			"frak" | "mathfrak" => single!(cursor, "frak").into(),
+			"frown" => "⌢".into(),
+			// G
+			"Game" => "⅁".into(),
+			"ge" | "geq" => ">=".into(),
+			"geqq" => "ge.equiv".into(),
+			"geqslant" => "gt.eq.slant".into(),
+			"gets" | "larr" | "leftarrow" => "<-".into(),
+			"gg" => ">>".into(),
+			"ggg" | "gggtr" => ">>>".into(),
+			"gnapprox" => "⪊".into(),
+			"gneq" => "⪈".into(),
+			"gneqq" => "gt.nequiv".into(),
+			"gnsim" => "gt.ntilde".into(),
+			"grave" => single!(cursor, "grave").into(),
+			"gt" => ">".into(),
+			"gtapprox" => "⪆".into(),
+			"gtreqless" => "gt.eq.lt".into(),
+			"gtreqqless" => "⪌".into(),
+			"gtrless" => "gt.lt".into(),
+			"gtrsim" => "gt.tilde".into(),
+			// H
+			"H" => single!(cursor, "acute.double").into(),
+			"Harr" | "hArr" | "Leftrightarrow" | "Lrarr" | "lrArr" => "<=>".into(),
+			"harr" | "leftrightarrow" | "lrarr" => "<->".into(),
+			"hat" | "widehat" => single!(cursor, "hat").into(),
+			"hbar" | "hslash" => "planck.reduce".into(),
+			"hbox" | "mathnormal" | "mathop" => cursor.next_param()?.to_typst()?.into(),
+			// lines at the start of matrix rows are taken by `matrix_args`
+			"hdashline" | "hline" => "".into(),
+			"hearts" | "heartsuit" => "♡".into(),
+			"hookleftarrow" => "arrow.l.hook".into(),
+			"hookrightarrow" => "arrow.r.hook".into(),
+			"hphantom" => format!("#box(height: 0pt, hide[${}$])", cursor.next_param()?.to_typst()?).into(),
+			"hspace" | "mskip" => single!(cursor, "#h").into(),
+			// I
+			"i" | "imath" => "dotless.i".into(),
+			"iff" | "Longleftrightarrow" => "<==>".into(),
+			"iiint" => "integral.triple".into(),
+			"iint" => "integral.double".into(),
+			"image" => "Im".into(),
+			"impliedby" | "Longleftarrow" => "<==".into(),
+			"implies" => "==>".into(),
+			"infin" | "infty" => "infinity".into(),
+			"injlim" => "#math.op(\"inj\\u{{2009}}lim\", limits: true)".into(),
+			"int" | "intop" => "integral".into(),
+			"intercal" => "⊺".into(),
+			"isin" => "in".into(),
+			// JK
+			"j" | "jmath" => "dotless.j".into(),
+			"KaTeX" => "\"KaTeX\"".into(),
+			"Ket" | "ket" => format!("lr(| {} angle.r)", cursor.next_param()?.to_typst()?).into(),
+			// L
+			"lang" | "langle" => "angle.l".into(),
+			"Larr" | "lArr" | "Leftarrow" => "arrow.l.double".into(),
+			"LaTeX" => "\"LaTeX\"".into(),
+			"lBrace" => "⦃".into(),
+			"lbrace" => "{".into(),
+			"lbrack" => "[".into(),
+			"lceil" => "⌈".into(),
+			// numbering is taken by `equation_to_typst`
+			"label" => {
+				cursor.next_param()?;
+				"".into()
+			}
+			"ldotp" => ".".into(),
+			"le" | "leq" => "<=".into(),
+			"leadsto" => "arrow.r.squiggly".into(),
+			"lfloor" => "⌊".into(),
+			"lgroup" => "turtle.l".into(),
+			"lhd" | "vartriangleleft" => "lt.tri".into(),
+			"limits" | "nolimits" => "".into(),
+			"ll" => "<<".into(),
+			"llap" => lap(format!("[{}]", text_to_typst(cursor.next_param()?.raw())?), "right").into(),
+			"llbracket" => "bracket.l.double".into(),
+			"llcorner" => "⌞".into(),
+			"Lleftarrow" => "arrow.l.triple".into(),
+			"lll" | "llless" => "<<<".into(),
+			"lnapprox" => "⪉".into(),
+			"lneq" => "⪇".into(),
+			"lneqq" => "lt.nequiv".into(),
+			"lnot" => "not".into(),
+			"lnsim" => "lt.ntilde".into(),
+			"longleftarrow" => "<--".into(),
+			"longleftrightarrow" => "<-->".into(),
+			"longmapsto" => "arrow.r.long.bar".into(),
+			"Longrightarrow" => "==>".into(),
+			"longrightarrow" => "-->".into(),
+			"looparrowleft" => "arrow.l.loop".into(),
+			"looparrowright" => "arrow.r.loop".into(),
+			"lor" | "vee" => "or".into(),
+			"lozenge" => "lozenge.stroked".into(),
+			"lparen" => "(".into(),
+			"lrcorner" => "⌟".into(),
+			"lq" => "quote.l.single".into(),
+			"Lsh" => "↰".into(),
+			"lt" => "<".into(),
+			"ltimes" => "times.l".into(),
+			"lVert" | "rVert" | "Vert" => "parallel".into(),
+			"lvert" | "rvert" | "vert" => "divides".into(),
+			// M
+			"mapsto" => "arrow.r.bar".into(),
+			"mathbb" => single!(cursor, "bb").into(),
+			"mathbf" => single!(cursor, "bold").into(),
+			"mathbin" => format!("#math.op(\"{}\")", typ::escape_string(cursor.next_param()?.raw())).into(),
+			"mathcal" => single!(cursor, "cal").into(),
+			"mathclap" => lap(format!("${}$", cursor.next_param()?.to_typst()?), "center").into(),
+			"mathclose" => format!("#h(0pt) {}", cursor.next_param()?.to_typst()?).into(),
+			"mathit" => single!(cursor, "italic").into(),
+			"mathllap" => lap(format!("${}$", cursor.next_param()?.to_typst()?), "right").into(),
+			"mathopen" => format!("{} #h(0pt)", cursor.next_param()?.to_typst()?).into(),
+			"mathring" | "r" => single!(cursor, "circle").into(),
+			"mathrlap" => lap(format!("${}$", cursor.next_param()?.to_typst()?), "left").into(),
+			"mathrm" => single!(cursor, "upright").into(),
+			"mathsf" => single!(cursor, "sans").into(),
+			"mathsterling" => "pound".into(),
+			"mathstrut" => "#hide(box(width: 0pt, \")\"))".into(),
+			"measuredangle" => "angle.arc".into(),
+			"medspace" => "space.med".into(),
+			"mho" => "ohm.inv".into(),
+			"mid" => "|".into(),
+			"middle" => single!(cursor, "mid").into(),
+			"minuso" => "⊖".into(),
+			"models" | "vDash" => "tack.r.double".into(),
+			"mp" => "minus.plus".into(),
+			// N
+			"N" | "natnums" => "NN".into(),
+			"natural" => "♮".into(),
+			"negmedspace" => "#h(-2em/9)".into(),
+			"ncong" => "tilde.equiv.not".into(),
+			"ne" | "neq" => "!=".into(),
+			"nearrow" => "arrow.tr".into(),
+			"neg" => "not".into(),
+			"negthickspace" => "#h(-5em/18)".into(),
+			"negthinspace" => "#h(-1em/6)".into(),
+			"nexist" => "exists.not".into(),
+			"ngeq" => "gt.eq.not".into(),
+			"ngtr" => "gt.not".into(),
+			"ni" | "owns" => "in.rev".into(),
+			"nLeftarrow" => "arrow.l.double.not".into(),
+			"nleftarrow" => "arrow.l.not".into(),
+			"nLeftrightarrow" => "arrow.l.r.double.not".into(),
+			"nleftrightarrow" => "arrow.l.r.not".into(),
+			"nleq" => "lt.eq.not".into(),
+			"nless" => "lt.not".into(),
+			"nmid" => "divides.not".into(),
+			"nobreakspace" => "space.nobreak".into(),
+			"nonumber" | "notag" => "".into(),
+			"notin" => "in.not".into(),
+			"notni" => "in.rev.not".into(),
+			"notparallel" => "parallel.not".into(),
+			"nprec" => "prec.not".into(),
+			"npreceq" => "prec.eq.not".into(),
+			"nRightarrow" => "arrow.r.double.not".into(),
+			"nrightarrow" => "arrow.r.not".into(),
+			"nsim" => "tilde.not".into(),
+			"nsubseteq" | "nsupseteq" => "subset.eq.not".into(),
+			"nsucc" => "succ.not".into(),
+			"nsucceq" => "succ.eq.not".into(),
+			"ntriangleleft" => "lt.tri.not".into(),
+			"ntrianglelefteq" => "lt.tri.eq.not".into(),
+			"ntriangleright" => "gt.tri.not".into(),
+			"ntrianglerighteq" => "gt.tri.eq.not".into(),
+			"nVDash" => "⊯".into(),
+			"nVdash" => "⊮".into(),
+			"nvDash" => "tack.r.double.not".into(),
+			"nvdash" => "tack.r.not".into(),
+			"nwarrow" => "arrow.tl".into(),
+			// O
+			"O" => "Ø".into(),
+			"o" => "ø".into(),
+			"odot" => "dot.circle".into(),
+			"OE" => "Œ".into(),
+			"oe" => "œ".into(),
+			"oiiint" => "integral.vol".into(),
+			"oiint" => "integral.surf".into(),
+			"oint" => "integral.cont".into(),
+			"ominus" => "minus.circle".into(),
+			"operatorname" => format!("#math.op(\"{}\")", typ::escape_string(cursor.next_param()?.raw())).into(),
+			"operatorname*" | "operatornamewithlimits" => format!(
+				"#math.op(\"{}\", limits: true)",
+				typ::escape_string(cursor.next_param()?.raw())
+			)
+			.into(),
+			"oplus" => "plus.circle".into(),
+			"origof" => "⊶".into(),
+			"oslash" => "⊘".into(),
+			"otimes" => "times.circle".into(),
+			"overbrace" => {
+				let param1 = cursor.next_param()?.to_typst()?;
+				match cursor.peek().map(|node| &node.kind) {
+					Some(Kind::Sup(param2)) => {
+						cursor.skip_whitespace();
+						cursor.next();
+						format!("overbrace({param1}, {})", cursor.script(param2)?).into()
+					}
+					_ => format!("overbrace({param1})").into(),
+				}
+			}
+			"overgroup" => accent!(cursor, "\\u{{0311}}").into(),
+			"overleftarrow" => single!(cursor, "arrow.l").into(),
+			"overleftharpoon" => accent!(cursor, "harpoon.lt").into(),
+			"overleftrightarrow" => accent!(cursor, "arrow.l.r").into(),
+			"overline" => single!(cursor, "overline").into(),
+			"overlinesegment" => accent!(cursor, "\\u{{20e9}}").into(),
+			"overrightarrow" | "vec" => single!(cursor, "arrow").into(),
+			"overrightharpoon" => accent!(cursor, "harpoon.rt").into(),
+			"overset" | "stackrel" => {
+				let top = cursor.next_param()?.to_typst()?;
+				format!("attach(limits({}), t: {top})", cursor.next_param()?.to_typst()?).into()
+			}
+			// P
+			"P" => "pilcrow".into(),
+			"partial" => "diff".into(),
+			"perp" => "bot".into(),
+			"phantom" => format!("#hide[${}$]", cursor.next_param()?.to_typst()?).into(),
+			"phi" => "phi.alt".into(),
+			"pitchfork" => "⋔".into(),
+			"plim" => "#math.op(\"plim\", limits: true)".into(),
+			"plusmn" | "pm" => "plus.minus".into(),
+			"pounds" => "pound".into(),
+			"precapprox" => "prec.approx".into(),
+			"preccurlyeq" => "prec.eq".into(),
+			"preceq" => "⪯".into(),
+			"precnapprox" => "prec.napprox".into(),
+			"precneqq" => "prec.nequiv".into(),
+			"precnsim" => "prec.ntilde".into(),
+			"precsim" => "prec.tilde".into(),
+			"prime" | "rq" => "'".into(),
+			"prod" => "product".into(),
+			"projlim" => "#math.op(\"proj\\u{{2009}}lim\", limits: true)".into(),
+			"propto" | "varpropto" => "prop".into(),
+			"pu" => mhchem::pu_to_typst(cursor.next_param()?.raw())?.into(),
+			// QR
+			"qquad" => "#h(2em)".into(),
+			"quad" => "space.quad".into(),
+			"R" => "RR".into(),
+			"raisebox" => format!(
+				"#text(baseline: -{})[{}]",
+				cursor.next_param()?.raw(),
+				cursor.next_param()?.to_typst()?
+			)
+			.into(),
+			"rang" | "rangle" => "angle.r".into(),
+			"Rarr" | "rArr" | "Rightarrow" => "=>".into(),
+			"rarr" | "rightarrow" | "to" => "->".into(),
+			"ratio" => ":".into(),
+			"rBrace" => "⦄".into(),
+			"rbrace" => "}".into(),
+			"rbrack" => "]".into(),
+			"rceil" => "⌉".into(),
+			"Reals" | "reals" => "RR".into(),
+			"restriction" => "harpoon.tr".into(),
+			"rfloor" => "⌋".into(),
+			"rgroup" => "turtle.r".into(),
+			"rhd" | "vartriangleright" => "gt.tri".into(),
+			"rightarrowtail" => ">->".into(),
+			"rightharpoondown" => "harpoon.rb".into(),
+			"rightharpoonup" => "harpoon.rt".into(),
+			"rightleftarrows" => "arrows.rl".into(),
+			"rightleftharpoons" => "harpoons.rtlb".into(),
+			"rightrightarrows" => "arrows.rr".into(),
+			"rightsquigarrow" => "arrow.r.squiggly".into(),
+			"rightthreetimes" => "times.three.r".into(),
+			"risingdotseq" => "≓".into(),
+			"rlap" => lap(format!("[{}]", text_to_typst(cursor.next_param()?.raw())?), "left").into(),
+			"rmoustache" => "⎱".into(),
+			"rparen" => ")".into(),
+			"rrbracket" => "bracket.r.double".into(),
+			"Rrightarrow" => "arrow.r.triple".into(),
+			"Rsh" => "↱".into(),
+			"rtimes" => "times.r".into(),
+			"rule" => match cursor.next_optional().map_or("", |raise| raise.raw()) {
+				"" => format!(
+					"#box(fill: black, width: {}, height: {})",
+					cursor.next_param()?.raw(),
+					cursor.next_param()?.raw()
+				)
+				.into(),
+				raise => format!(
+					"#box(inset: (bottom: {}), box(fill: black, width: {}, height: {}))",
+					raise,
+					cursor.next_param()?.raw(),
+					cursor.next_param()?.raw(),
+				)
+				.into(),
+			},
+			// S
+			"S" | "sect" => "section".into(),
+			"searrow" => "arrow.br".into(),
+			"Set" | "set" => format!("{{{}}}", cursor.next_param()?.to_typst()?).into(),
+			"setminus" | "smallsetminus" => "without".into(),
+			"sharp" => "♯".into(),
+			"sim" => "tilde.op".into(),
+			"simcolon" => "tilde.op:".into(),
+			"simcoloncolon" => "tilde.op::".into(),
+			"simeq" => "tilde.eq".into(),
+			"sh" => "#math.op(\"sh\")".into(),
+			"smallint" => "inline(integral)".into(),
+			"smallsmile" => "⌣".into(),
+			"sout" => single!(cursor, "cancel", "angle: #90deg").into(),
+			"spades" | "spadesuit" => "suit.spade".into(),
+			"sphericalangle" => "angle.spheric".into(),
+			"sqcap" => "sect.sq".into(),
+			"sqcup" => "union.sq".into(),
+			"square" => "square.stroked".into(),
+			"sqrt" => match cursor.next_optional().filter(|index| !index.nodes.is_empty()) {
+				Some(index) => format!("root({}, {})", index.to_typst()?, cursor.next_param()?.to_typst()?).into(),
+				None => single!(cursor, "sqrt").into(),
+			},
+			"sqsubset" => "subset.sq".into(),
+			"sqsubseteq" => "subset.eq.sq".into(),
+			"sqsupset" => "superset.sq".into(),
+			"sqsupseteq" => "superset.eq.sq".into(),
+			"ss" => "ß".into(),
+			"star" => "star.op".into(),
+			"sub" => "subset".into(),
+			"sube" | "subseteq" => "subset.eq".into(),
+			"Subset" => "subset.double".into(),
+			"subseteqq" => "⫅".into(),
+			"subsetneq" | "varsubsetneq" => "subset.neq".into(),
+			"subsetneqq" | "varsubsetneqq" => "⫋".into(),
+			"substack" => lines_to_typst(src, cursor.next_param()?.inner())?
+				.trim()
+				.to_string()
+				.into(),
+			"succapprox" => "succ.approx".into(),
+			"succcurlyeq" => "succ.eq".into(),
+			"succeq" => "⪰".into(),
+			"succnapprox" => "succ.napprox".into(),
+			"succneqq" => "succ.nequiv".into(),
+			"succnsim" => "succ.ntilde".into(),
+			"supe" | "supseteq" => "supset.eq".into(),
+			"Supset" => "superset.double".into(),
+			"supseteqq" => "⫆".into(),
+			"supsetneq" | "varsupsetneq" => "superset.neq".into(),
+			"supsetneqq" | "varsupsetneqq" => "⫌".into(),
+			"surd" => "√".into(),
+			"swarrow" => "arrow.bl".into(),
+			// T
+			"tag" => {
+				if cursor.nodes.first().is_some_and(|node| node.kind == Kind::Char('*')) {
+					cursor.next();
+				}
+				cursor.next_param()?;
+				"".into()
+			}
+			"tbinom" => double!(cursor, "inline", "binom").into(),
+			"TeX" => "\"TeX\"".into(),
+			"text" | "textmd" | "textnormal" | "textrm" | "textup" => {
+				format!("#[{}]", text_to_typst(cursor.next_param()?.raw())?).into()
+			}
+			"textbf" => format!("bold(#[{}])", text_to_typst(cursor.next_param()?.raw())?).into(),
+			"textcolor" => format!(
+				"#text(fill: {})[{}]",
+				color_to_typst(cursor.next_param()?.raw()),
+				text_to_typst(cursor.next_param()?.raw())?
+			)
+			.into(),
+			"textit" => format!("italic(#[{}])", text_to_typst(cursor.next_param()?.raw())?).into(),
+			"textsf" => format!("sans(#[{}])", text_to_typst(cursor.next_param()?.raw())?).into(),
+			"textstyle" => format!("inline({})", cursor.rest()?).into(),
+			"texttt" => format!("mono(#[{}])", text_to_typst(cursor.next_param()?.raw())?).into(),
+			"tfrac" => double!(cursor, "inline", "frac").into(),
+			"th" => "#math.op(\"th\")".into(),
+			"thetasym" => "theta.alt".into(),
+			"thickapprox" => "bold(approx)".into(),
+			"thicksim" => "bold(tilde)".into(),
+			"thickspace" => "#h(5em/18)".into(),
+			"thinspace" => "space.sixth".into(),
+			"tilde" | "widetilde" => single!(cursor, "tilde").into(),
+			"triangledown" => "triangle.stroked.b".into(),
+			"triangleleft" => "triangle.stroked.l".into(),
+			"trianglelefteq" => "lt.tri.eq".into(),
+			"triangleq" => "eq.delta".into(),
+			"triangleright" => "triangle.stroked.r".into(),
+			"trianglerighteq" => "gt.tri.eq".into(),
+			"twoheadleftarrow" => "<<-".into(),
+			"twoheadrightarrow" => "->>".into(),
+			// U
+			"Uarr" | "uArr" | "Uparrow" => "arrow.t.double".into(),
+			"uarr" | "uparrow" => "arrow.t".into(),
+			"ulcorner" => "⌜".into(),
+			"underbar" | "underline" => single!(cursor, "underline").into(),
+			"underbrace" => {
+				let param1 = cursor.next_param()?.to_typst()?;
+				match cursor.peek().map(|node| &node.kind) {
+					Some(Kind::Sub(param2)) => {
+						cursor.skip_whitespace();
+						cursor.next();
+						format!("underbrace({param1}, {})", cursor.script(param2)?).into()
+					}
+					_ => format!("underbrace({param1})").into(),
+				}
+			}
+			"undergroup" => accent!(cursor, "\\u{{032e}}").into(),
+			"underleftrightarrow" => accent!(cursor, "\\u{{034d}}").into(),
+			"underset" => {
+				let bottom = cursor.next_param()?.to_typst()?;
+				format!("attach(limits({}), b: {bottom})", cursor.next_param()?.to_typst()?).into()
+			}
+			"unlhd" => "lt.tri.eq".into(),
+			"unrhd" => "gt.tri.eq".into(),
+			"Updownarrow" => "arrow.t.b.double".into(),
+			"updownarrow" => "arrow.t.b".into(),
+			"upharpoonleft" => "harpoon.tl".into(),
+			"upharpoonright" => "harpoon.tr".into(),
+			"uplus" => "union.plus".into(),
+			"upuparrows" => "arrows.tt".into(),
+			"urcorner" => "⌝".into(),
+			// V
+			"varDelta" => "italic(Delta)".into(),
+			"varepsilon" => "italic(epsilon)".into(),
+			"varGamma" => "italic(Gamma)".into(),
+			"varkappa" => "italic(kappa)".into(),
+			"varnothing" => "italic(nothing)".into(),
+			"varOmega" => "italic(Omega)".into(),
+			"varPhi" => "italic(Phi)".into(),
+			"varphi" => "italic(phi)".into(),
+			"varPi" => "italic(Pi)".into(),
+			"varpi" => "italic(pi.alt)".into(),
+			"varPsi" => "italic(Psi)".into(),
+			"varrho" => "italic(rho.alt)".into(),
+			"varSigma" => "italic(Sigma)".into(),
+			"varsigma" => "italic(sigma.alt)".into(),
+			"varTheta" => "italic(Theta)".into(),
+			"vartheta" => "italic(theta.alt)".into(),
+			"varUpsilon" => "italic(Upsilon)".into(),
+			"varXi" => "italic(Xi)".into(),
+			"vcentcolon" => ":".into(),
+			"Vdash" => "⊩".into(),
+			"vdash" => "tack.r".into(),
+			"vdots" => "dots.v".into(),
+			"veebar" => "⊻".into(),
+			"vphantom" => format!("#box(width: 0pt, hide[${}$])", cursor.next_param()?.to_typst()?).into(),
+			"Vvdash" => "⊪".into(),
+			// W
+			"wedge" | "land" => "and".into(),
+			"weierp" | "wp" => "℘".into(),
+			"wr" => "wreath".into(),
+			// X
+			"xcancel" => single!(cursor, "cancel", "cross: #true").into(),
+			"xhookleftarrow" => single!(cursor, "xarrow", "sym: arrow.l.hook").into(),
+			"xhookrightarrow" => single!(cursor, "xarrow", "sym: arrow.r.hook").into(),
+			"xLeftarrow" => single!(cursor, "xarrow", "sym: arrow.l.double").into(),
+			"xleftarrow" => single!(cursor, "xarrow", "sym: arrow.l").into(),
+			"xleftharpoondown" => single!(cursor, "xarrow", "sym: harpoon.lb").into(),
+			"xleftharpoonup" => single!(cursor, "xarrow", "sym: harpoon.lt").into(),
+			"xLeftrightarrow" => single!(cursor, "xarrow", "sym: arrow.l.r.double").into(),
+			"xleftrightarrow" => single!(cursor, "xarrow", "sym: arrow.l.r").into(),
+			"xleftrightharpoons" => single!(cursor, "xarrow", "sym: harpoons.ltrb").into(),
+			"xlongequal" => single!(cursor, "xarrow", "sym: equal").into(),
+			"xmapsto" => single!(cursor, "xarrow", "sym: arrow.r.bar").into(),
+			"xRightarrow" => single!(cursor, "xarrow", "sym: arrow.r.double").into(),
+			"xrightarrow" => single!(cursor, "xarrow", "sym: arrow.r").into(),
+			"xrightharpoondown" => single!(cursor, "xarrow", "sym: harpoon.rb").into(),
+			"xrightharpoonup" => single!(cursor, "xarrow", "sym: harpoon.rt").into(),
+			"xrightleftharpoons" => single!(cursor, "xarrow", "sym: harpoons.rtlb").into(),
+			"xtwoheadleftarrow" => single!(cursor, "xarrow", "sym: arrow.l.twohead").into(),
+			"xtwoheadrightarrow" => single!(cursor, "xarrow", "sym: arrow.r.twohead").into(),
+			// YZ
+			"Z" => "ZZ".into(),
+			word => {
+				if !TYPST_NAMES.contains(&word) {
+					unknown_command(word);
+				}
+				Cow::Owned(word.to_string())
+			}
+		},
+		Kind::Sub(param) | Kind::Sup(param) => {
+			let op = if let Kind::Sub(_) = node.kind { '_' } else { '^' };
+			match &param.kind {
+				Kind::Group(nodes) => format!("{op}({})", nodes_to_typst(src, nodes)?).into(),
+				_ => {
+					let rest = cursor.nodes.len();
+					let script = cursor.script(param)?;
+					// a command with arguments can convert to more than a single term
+					if cursor.nodes.len() < rest {
+						format!("{op}({script})").into()
+					} else {
+						format!("{op}{script}").into()
+					}
+				}
+			}
+		}
+		Kind::Group(nodes) => nodes_to_typst(src, nodes)?.into(),
+		Kind::Environment { name, body } => environment_to_typst(src, name, body, node.span.start)?.into(),
+		Kind::LeftRight { left, body, right } => format!(
+			"lr({}{}{})",
+			delimiter_to_typst(src, left)?,
+			nodes_to_typst(src, body)?,
+			delimiter_to_typst(src, right)?
+		)
+		.into(),
+		Kind::Comment(comment) => format!("//{comment}\n").into(),
+		// scripts attach to what comes before the whitespace
+		Kind::Char(c)
+			if c.is_whitespace()
+				&& matches!(
+					cursor.nodes.first().map(|node| &node.kind),
+					Some(Kind::Sub(_) | Kind::Sup(_))
+				) =>
+		{
+			"".into()
+		}
+		Kind::Char('~') => "space.nobreak".into(),
+		Kind::Char(c @ ('/' | '"')) => format!("\\{c}").into(),
+		Kind::Char(c) => c.to_string().into(),
+	})
+}
+
+/// Convert a group split by an infix command like `{a \over b}`.
+fn infix_to_typst(src: &str, nodes: &[Node], infix: usize) -> Result<String, ParseError> {
+	let Kind::Command(name) = nodes[infix].kind else {
+		unreachable!()
+	};
+	let numerator = nodes_to_typst(src, &nodes[..infix])?;
+	let mut cursor = Cursor::new(src, &nodes[infix + 1..], nodes[infix].span.start);
+	// `\above` takes the rule thickness first, Typst can only draw the default rule or none
+	let ruled = name != "above" || {
+		cursor.skip_whitespace();
+		let thickness = cursor.chars_while(|c| c.is_ascii_digit() || c == '.');
+		cursor.chars_while(|c| c.is_ascii_alphabetic());
+		thickness.parse::<f64>() != Ok(0.0)
+	};
+	let (numerator, denominator) = (numerator.trim(), cursor.rest()?);
+	let denominator = denominator.trim();
+	Ok(match name {
+		"above" | "over" if ruled => format!("frac({numerator}, {denominator})"),
+		"brace" => format!("mat(delim: \"{{\", {numerator}; {denominator})"),
+		"brack" => format!("mat(delim: \"[\", {numerator}; {denominator})"),
+		"choose" => format!("binom({numerator}, {denominator})"),
+		_ => format!("mat(delim: #none, {numerator}; {denominator})"),
+	})
+}
+
+/// Delimiter of `\left` and `\right`, `.` is none.
+fn delimiter_to_typst(src: &str, node: &Node) -> Result<String, ParseError> {
+	match node.kind {
+		Kind::Char('.') => Ok(String::new()),
+		_ => nodes_to_typst(src, std::slice::from_ref(node)),
+	}
+}
+
+fn environment_to_typst(src: &str, name: &str, body: &[Node], offset: usize) -> Result<String, ParseError> {
+	let mut cursor = Cursor::new(src, body, offset);
+	// starred matrices take the column alignment as an optional argument
+	let matrix = |mut cursor: Cursor, delim: &str| -> Result<String, ParseError> {
+		let align = match name.ends_with('*') {
+			true => cursor
+				.next_optional()
+				.and_then(|align| align.raw().trim().chars().next()),
+			false => None,
+		};
+		Ok(format!(
+			"mat(delim: {delim}, {}{})",
+			matrix_args(cursor.nodes, align, &[]),
+			matrix_to_typst(src, cursor.nodes)?
+		))
+	};
+	let rows = |nodes| -> Result<String, ParseError> {
+		Ok(matrix_rows(nodes)
+			.into_iter()
+			.map(|row| nodes_to_typst(src, row))
+			.collect::<Result<Vec<_>, _>>()?
+			.join(","))
+	};
+	// numbering is added by `equation_to_typst`
+	if let Some(display) = display_to_typst(src, name, body, offset) {
+		return Ok(format!("$${}$$", display?));
+	}
+	Ok(match name {
+		"array" | "darray" => {
+			let (columns, vlines) = column_spec(cursor.next_param()?.raw());
+			// `mat` aligns all columns the same
+			let align = columns.iter().all_equal_value().ok().copied();
+			format!(
+				"mat(delim: #none, {}{})",
+				matrix_args(cursor.nodes, align, &vlines),
+				matrix_to_typst(src, cursor.nodes)?
+			)
+		}
+		"Bmatrix" | "Bmatrix*" => matrix(cursor, "\"{\"")?,
+		"bmatrix" | "bmatrix*" => matrix(cursor, "\"[\"")?,
+		"cases" | "dcases" => format!("cases({})", rows(body)?),
+		// TODO: begin{CD}
+		"CD" => format!("CD({})", parser::raw(src, body)),
+		"matrix" | "matrix*" => matrix(cursor, "#none")?,
+		"pmatrix" | "pmatrix*" => matrix(cursor, "\"(\"")?,
+		"rcases" => format!("cases(reverse: #true, {})", rows(body)?),
+		"smallmatrix" => format!(
+			"inline(mat(delim: #none, {}{}))",
+			matrix_args(body, None, &[]),
+			matrix_to_typst(src, body)?
+		),
+		"Vmatrix" | "Vmatrix*" => matrix(cursor, "\"||\"")?,
+		"vmatrix" | "vmatrix*" => matrix(cursor, "\"|\"")?,
+		_ => nodes_to_typst(src, body)?,
+	})
+}
+
+/// Body of environments that are block equations themselves, like `align`.
+fn display_to_typst(src: &str, name: &str, body: &[Node], offset: usize) -> Option<Result<String, ParseError>> {
+	match name {
+		"align" | "align*" | "aligned" | "equation" | "equation*" | "flalign" | "flalign*" | "gather" | "gather*"
+		| "gathered" | "multline" | "multline*" | "split" => Some(lines_to_typst(src, body)),
+		"alignat" | "alignat*" | "alignedat" => {
+			let mut cursor = Cursor::new(src, body, offset);
+			Some(cursor.next_param().and_then(|_| lines_to_typst(src, cursor.nodes)))
+		}
+		_ => None,
+	}
+}
+
+/// Convert lines of a multi-line equation, `&` are kept as alignment points.
+fn lines_to_typst(src: &str, nodes: &[Node]) -> Result<String, ParseError> {
+	Ok(matrix_rows(nodes)
+		.into_iter()
+		.map(|row| nodes_to_typst(src, row))
+		.map_ok(|row| match row.starts_with(char::is_whitespace) {
+			true => row,
+			// `\` followed by a character escapes it
+			false => format!(" {row}"),
+		})
+		.collect::<Result<Vec<_>, _>>()?
+		.join("\\"))
+}
+
+/// Zero-width box for `\llap` and the like, `align` is the side the content sticks out from.
+fn lap(content: String, align: &str) -> String {
+	format!("#box(width: 0pt, align({align}, {content}))")
 }
 
 fn color_to_typst(color: &str) -> Cow<'_, str> {
@@ -1046,7 +1929,7 @@ fn color_to_typst(color: &str) -> Cow<'_, str> {
 	}
 }
 
-pub fn text_to_typst(text: &str) -> Result<String, ScannerError> {
+pub fn text_to_typst(text: &str) -> Result<String, ParseError> {
 	let mut scanner = Scanner::new(text);
 	let mut ret = String::with_capacity(text.len());
 	while let Some(c) = scanner.next() {
@@ -1075,7 +1958,13 @@ pub fn text_to_typst(text: &str) -> Result<String, ScannerError> {
 				"textregistered" => "®".into(),
 				"textsterling" => "#sym.pound".into(),
 				"textunderscore" => "\\_".into(),
-				word => word.to_owned().into(),
+				word => {
+					// control symbols like `\%` are left to the next character
+					if !word.is_empty() {
+						unknown_command(word);
+					}
+					word.to_owned().into()
+				}
 			},
 			'$' => {
 				let math: Cow<str> = scanner.by_ref().take_while(|&c| c != '$').collect();
@@ -1091,34 +1980,23 @@ pub fn text_to_typst(text: &str) -> Result<String, ScannerError> {
 /// split with `\\`, then split with `&` (not `\&`), finally process element by element
 ///
 /// for example:
-/// ```
+/// ```text
 /// a& b\\
 /// c& d\\
 /// ```
 ///
 /// will be converted to:
-/// ```
+/// ```text
 /// a, b;
 /// c, d
 /// ```
-fn matrix_to_typst(content: String) -> Result<String, ScannerError> {
-	Ok(Regex::new(r"\\\\|\\cr")
-		.unwrap()
-		.split(&content)
+fn matrix_to_typst(src: &str, nodes: &[Node]) -> Result<String, ParseError> {
+	Ok(matrix_rows(nodes)
+		.into_iter()
 		.map(|row| {
-			let mut s: Vec<String> = row.split('&').map(|s| s.into()).collect();
-			let mut i = 0;
-			while i < s.len() {
-				if s[i].ends_with('\\') {
-					let temp = s[i + 1].clone();
-					s[i].push('&');
-					s[i] += &temp;
-					s.remove(i + 1);
-				}
-				i += 1;
-			}
-			Ok(s.iter()
-				.map(|col| latex_to_typst(col.into()))
+			Ok(strip_hlines(row)
+				.split(|node| node.kind == Kind::Char('&'))
+				.map(|cell| nodes_to_typst(src, cell))
 				.collect::<Result<Vec<_>, _>>()?
 				.join(","))
 		})
@@ -1126,6 +2004,115 @@ fn matrix_to_typst(content: String) -> Result<String, ScannerError> {
 		.join(";"))
 }
 
+/// Split rows of an environment with `\\` or `\cr`, without the spacing in `\\[2pt]` and the empty row after the last
+/// `\\`.
+fn matrix_rows<'a, 'b>(nodes: &'b [Node<'a>]) -> Vec<&'b [Node<'a>]> {
+	let mut rows: Vec<_> = nodes
+		.split(|node| matches!(node.kind, Kind::Command("\\" | "cr")))
+		.enumerate()
+		.map(|(i, row)| {
+			if i > 0
+				&& let Some(start) = row.iter().position(|node| !node.is_whitespace())
+				&& row[start].kind == Kind::Char('[')
+				&& let Some(len) = row[start..].iter().position(|node| node.kind == Kind::Char(']'))
+			{
+				&row[start + len + 1..]
+			} else {
+				row
+			}
+		})
+		.collect();
+	if rows.len() > 1
+		&& rows
+			.last()
+			.is_some_and(|row| strip_hlines(row).iter().all(Node::is_whitespace))
+	{
+		rows.pop();
+	}
+	rows
+}
+
+/// Row without `\hline` and `\hdashline` at its start.
+fn strip_hlines<'a, 'b>(row: &'b [Node<'a>]) -> &'b [Node<'a>] {
+	let is_hline = |node: &Node| matches!(node.kind, Kind::Command("hdashline" | "hline"));
+	let leading = row
+		.iter()
+		.take_while(|node| node.is_whitespace() || is_hline(node))
+		.count();
+	let start = row[..leading].iter().rposition(is_hline).map_or(0, |end| end + 1);
+	&row[start..]
+}
+
+/// Column alignments and the columns before vertical rules (`|` or `:`) in an array column spec like `c|l:r`.
+fn column_spec(spec: &str) -> (Vec<char>, Vec<usize>) {
+	let mut scanner = Scanner::new(spec);
+	let mut columns = Vec::new();
+	let mut vlines = Vec::new();
+	while let Some(c) = scanner.next() {
+		match c {
+			'c' | 'l' | 'r' => columns.push(c),
+			// paragraph columns with a width
+			'b' | 'm' | 'p' => {
+				scanner.next_arg();
+				columns.push('l');
+			}
+			'|' | ':' => vlines.push(columns.len()),
+			// `@{...}` and `!{...}` replace the space between columns
+			'!' | '@' => {
+				scanner.next_arg();
+			}
+			'*' => {
+				let count = scanner.next_arg().and_then(|n| n.trim().parse().ok()).unwrap_or(0);
+				let (repeated, lines) = column_spec(&scanner.next_arg().unwrap_or_default().repeat(count));
+				vlines.extend(lines.into_iter().map(|line| line + columns.len()));
+				columns.extend(repeated);
+			}
+			_ => {}
+		}
+	}
+	(columns, vlines)
+}
+
+/// Arguments of `mat` for the column alignment, `\hline` at the start of rows and vertical rules, each followed by
+/// `, `. Lines on the border are left out as `augment` only draws them between rows and columns.
+fn matrix_args(nodes: &[Node], align: Option<char>, vlines: &[usize]) -> String {
+	let rows = matrix_rows(nodes);
+	let columns = rows
+		.iter()
+		.map(|row| row.split(|node| node.kind == Kind::Char('&')).count())
+		.max()
+		.unwrap_or_default();
+	let hlines: Vec<_> = (1..rows.len())
+		.filter(|&i| strip_hlines(rows[i]).len() < rows[i].len())
+		.collect();
+	let vlines: Vec<_> = vlines
+		.iter()
+		.copied()
+		.filter(|&line| 0 < line && line < columns)
+		.dedup()
+		.collect();
+	let lines = |name: &str, lines: &[usize]| match lines {
+		[] => None,
+		[line] => Some(format!("{name}: {line}")),
+		lines => Some(format!("{name}: ({})", lines.iter().join(", "))),
+	};
+
+	let mut args = String::new();
+	match align {
+		Some('l') => args += "align: left, ",
+		Some('r') => args += "align: right, ",
+		_ => {}
+	}
+	let augment: Vec<_> = [lines("hline", &hlines), lines("vline", &vlines)]
+		.into_iter()
+		.flatten()
+		.collect();
+	if !augment.is_empty() {
+		args += &format!("augment: ({}), ", augment.join(", "));
+	}
+	args
+}
+
 #[cfg(test)]
 mod function_tests {
 	use super::*;
@@ -1147,12 +2134,14 @@ mod function_tests {
 
 	#[test]
 	fn next_param_test() {
-		let mut scanner = Scanner::new("\n\t\\land\\%=3aa\\\\");
+		let src = "\n\t\\land\\%=3aa\\\\";
+		let nodes = parser::parse(src).unwrap();
+		let mut cursor = Cursor::new(src, &nodes, 0);
 		let mut count = 0;
 		let assert = ["\\land", "\\%", "=", "3", "a", "a", "\\\\"];
-		while let Ok(c) = scanner.next_param() {
-			// println!("{}", c);
-			assert_eq!(c, assert[count]);
+		while let Ok(c) = cursor.next_param() {
+			// println!("{}", c.raw());
+			assert_eq!(c.raw(), assert[count]);
 			count += 1;
 		}
 	}
@@ -1167,12 +2156,20 @@ mod function_tests {
 
 	#[test]
 	fn matrix_test1() {
-		assert_eq!(matrix_to_typst("a& b\\\\\nc& d".to_string()).unwrap(), "a, b;\nc, d")
+		let src = "a& b\\\\\nc& d";
+		assert_eq!(
+			matrix_to_typst(src, &parser::parse(src).unwrap()).unwrap(),
+			"a, b;\nc, d"
+		)
 	}
 
 	#[test]
 	fn matrix_test2() {
-		assert_eq!(matrix_to_typst("a& b\\cr\nc& d".to_string()).unwrap(), "a, b;\nc, d")
+		let src = "a& b\\cr\nc& d";
+		assert_eq!(
+			matrix_to_typst(src, &parser::parse(src).unwrap()).unwrap(),
+			"a, b;\nc, d"
+		)
 	}
 }
 #[cfg(test)]
@@ -1220,4 +2217,217 @@ mod tests {
 			"mat(delim: #none, \na, b;\nc, d\n)"
 		);
 	}
+
+	#[test]
+	fn user_macros() {
+		reset_macros();
+		assert_eq!(
+			latex_to_typst("\\newcommand{\\R}{\\mathbb{R}}\\def\\vec#1{\\mathbf{#1}}".into()).unwrap(),
+			""
+		);
+		// definitions are kept for later equations
+		assert_eq!(
+			latex_to_typst("x \\in \\R^2, \\vec x".into()).unwrap(),
+			"x in bb(R)^2, bold(x)"
+		);
+		assert_eq!(
+			latex_to_typst("\\newcommand\\norm[2][2]{\\|#2\\|_#1}\\norm{v} \\norm[\\infty]{v}".into()).unwrap(),
+			"||v||_(2) ||v||_(infinity)"
+		);
+		assert_eq!(
+			latex_to_typst("\\let\\oldvec=\\vec \\renewcommand{\\vec}[1]{\\oldvec{#1}'} \\vec{y}".into()).unwrap(),
+			"  bold(y)'"
+		);
+		reset_macros();
+		assert_eq!(latex_to_typst("\\R".into()).unwrap(), "RR");
+	}
+
+	#[test]
+	fn tree() {
+		assert_eq!(
+			latex_to_typst("\\left( \\left[ x_\\alpha \\right] \\right.".into()).unwrap(),
+			"lr(( lr([ x_alpha ]) )"
+		);
+		assert_eq!(
+			latex_to_typst("\\begin{pmatrix}\\frac{a}{b} & {c \\& d}\\end{pmatrix}".into()).unwrap(),
+			"mat(delim: \"(\", frac(a, b) , c \\& d)"
+		);
+		assert!(matches!(
+			latex_to_typst("x^".into()),
+			Err(ParseError::UnexpectedEof { offset: 1, .. })
+		));
+		// offsets after a macro definition are in the expanded LaTeX
+		assert_eq!(
+			latex_to_typst("\\def\\a{x} \\a^".into()).unwrap_err().to_string(),
+			"Unexpected end of input while parsing argument at byte 2 of the LaTeX with macros expanded"
+		);
+		reset_macros();
+	}
+
+	#[test]
+	fn scripts() {
+		// commands in scripts take the following arguments
+		let cases = [
+			("x_\\text{max}", "x_(#[max])"),
+			("e^\\mathrm{i}", "e^(upright(i))"),
+			("\\sum_\\mathrm{i} x", "sum_(upright(i)) x"),
+			("x^\\frac12", "x^(frac(1, 2))"),
+			("x_\\mathbf{v}", "x_(bold(v))"),
+			("a^\\sqrt{2}", "a^(sqrt(2))"),
+			("\\overbrace{a+b}^\\text{n} c", "overbrace(a+b, #[n]) c"),
+			("x^\\prime y", "x^' y"),
+		];
+		for (latex, typst) in cases {
+			assert_eq!(latex_to_typst(latex.into()).unwrap(), typst, "{latex}");
+		}
+	}
+
+	#[test]
+	fn switches() {
+		assert_eq!(
+			latex_to_typst("a {\\bf b c} \\displaystyle \\frac12".into()).unwrap(),
+			"a bold( b c) display( frac(1, 2))"
+		);
+		assert_eq!(
+			latex_to_typst("\\left( \\color{#ff0000} x \\right) y".into()).unwrap(),
+			"lr(( #text(fill: rgb(\"#ff0000\"))[$ x $])) y"
+		);
+		assert_eq!(
+			latex_to_typst("\\begin{matrix} \\large a & b \\end{matrix}".into()).unwrap(),
+			"mat(delim: #none,  #text(size: 1.2em)[$ a $], b )"
+		);
+	}
+
+	#[test]
+	fn infix() {
+		assert_eq!(latex_to_typst("{a+1 \\over b} = c".into()).unwrap(), "frac(a+1, b) = c");
+		assert_eq!(
+			latex_to_typst("\\sum {n \\choose k} {n \\brace k} {n \\brack k}".into()).unwrap(),
+			"sum binom(n, k) mat(delim: \"{\", n; k) mat(delim: \"[\", n; k)"
+		);
+		assert_eq!(
+			latex_to_typst("{a \\atop b} {a \\above 0pt b} {a \\above 1.5pt b}".into()).unwrap(),
+			"mat(delim: #none, a; b) mat(delim: #none, a; b) frac(a, b)"
+		);
+		assert_eq!(latex_to_typst("x \\over y".into()).unwrap(), "frac(x, y)");
+	}
+
+	#[test]
+	fn numbering() {
+		assert_eq!(
+			equation_to_typst("\\begin{equation}x\\end{equation}").unwrap(),
+			"$ x $\n"
+		);
+		assert_eq!(
+			equation_to_typst("E = mc^2 \\label{eq:energy}").unwrap(),
+			"#math.equation(block: true, numbering: \"(1)\", $ E = m c^2 $) <eq:energy>\n"
+		);
+		assert_eq!(
+			equation_to_typst("x \\tag*{A}").unwrap(),
+			"#math.equation(block: true, numbering: _ => \"A\", $ x $)\n#counter(math.equation).update(n => n - 1)\n"
+		);
+		// rows with their own labels are separate equations
+		assert_eq!(
+			equation_to_typst("\\begin{align}a &= b \\label{a} \\\\ c &= d \\tag{1'} \\\\ e &= f\\end{align}").unwrap(),
+			"#math.equation(block: true, numbering: \"(1)\", $ a &= b $) <a>\n\
+			 #math.equation(block: true, numbering: _ => \"(1')\", $ c &= d $)\n\
+			 #counter(math.equation).update(n => n - 1)\n\
+			 $ e &= f $\n"
+		);
+		assert_eq!(
+			equation_labels("\\begin{align}a \\label{a} \\\\ b \\label{b}\\end{align}"),
+			["a", "b"]
+		);
+		assert_eq!(
+			latex_to_typst("\\eqref{a} \\ref{b c}".into()).unwrap(),
+			"#ref(<a>) #ref(label(\"b c\"))"
+		);
+	}
+
+	#[test]
+	fn array() {
+		assert_eq!(
+			latex_to_typst(
+				"\\begin{array}{c|c:c} \\hline a & b & c \\\\ \\hline d & e & f \\\\ \\hline \\end{array}".into()
+			)
+			.unwrap(),
+			"mat(delim: #none, augment: (hline: 1, vline: (1, 2)),  a , b , c ; d , e , f )"
+		);
+		assert_eq!(
+			latex_to_typst("\\begin{array}{|*{2}{r}|} 1 & 2 \\end{array}".into()).unwrap(),
+			"mat(delim: #none, align: right,  1 , 2 )"
+		);
+		assert_eq!(
+			latex_to_typst("\\begin{pmatrix*}[l] a \\\\[2pt] b \\end{pmatrix*}".into()).unwrap(),
+			"mat(delim: \"(\", align: left,  a ; b )"
+		);
+		assert_eq!(
+			equation_to_typst("\\begin{align*} a &= b \\\\[1ex] c &= d \\\\ \\end{align*}").unwrap(),
+			"$ a &= b \\ c &= d $\n"
+		);
+	}
+
+	#[test]
+	fn stacking() {
+		assert_eq!(
+			latex_to_typst("\\underset{x}{\\arg\\min} f(x)".into()).unwrap(),
+			"attach(limits(arg min), b: x) f(x)"
+		);
+		assert_eq!(
+			latex_to_typst("\\overset{\\text{def}}{=} \\stackrel{!}{=}".into()).unwrap(),
+			"attach(limits(=), t: #[def]) attach(limits(=), t: !)"
+		);
+		assert_eq!(
+			latex_to_typst("\\sum_{\\substack{i<n\\\\j<m}} a_{ij}".into()).unwrap(),
+			"sum_(i<n\\ j<m) a_(i j)"
+		);
+		assert_eq!(
+			latex_to_typst("\\llap{--} \\rlap{/} \\mathllap{x} \\mathrlap{y} \\mathclap{z}".into()).unwrap(),
+			"#box(width: 0pt, align(right, [--])) #box(width: 0pt, align(left, [/])) \
+			 #box(width: 0pt, align(right, $x$)) #box(width: 0pt, align(left, $y$)) \
+			 #box(width: 0pt, align(center, $z$))"
+		);
+	}
+
+	#[test]
+	fn typst_names() {
+		// kept sorted and unique, so names of newer Typst versions are easy to add
+		assert!(TYPST_NAMES.is_sorted());
+		assert!(TYPST_NAMES.windows(2).all(|names| names[0] != names[1]));
+		assert!(
+			TYPST_NAMES
+				.iter()
+				.all(|name| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphabetic()))
+		);
+	}
+
+	#[test]
+	fn unknown_commands() {
+		set_cell(3);
+		assert_eq!(
+			latex_to_typst("\\alpha \\unknowncmd x + \\unknowncmd".into()).unwrap(),
+			"alpha unknowncmd x + unknowncmd"
+		);
+		text_to_typst("\\unknowntext{a} \\%").unwrap();
+		let unknown = take_unknown_commands();
+		assert_eq!(unknown.get("unknowncmd"), Some(&vec![3, 3]));
+		assert_eq!(unknown.get("unknowntext"), Some(&vec![3]));
+		assert!(!unknown.contains_key("alpha"));
+		assert!(!unknown.contains_key(""));
+	}
+
+	#[test]
+	fn latex_errors() {
+		set_cell(2);
+		assert_eq!(inline_math("x^"), "#latex-error(\"x^\");");
+		assert_eq!(inline_math("x"), "$x$");
+		assert_eq!(
+			take_latex_errors(),
+			[(
+				2,
+				"Unexpected end of input while parsing argument at byte 1".to_string()
+			)]
+		);
+		assert!(take_latex_errors().is_empty());
+	}
 }
@@ -0,0 +1,435 @@
+//! mhchem `\ce` and `\pu`: chemical equations and physical units. Element symbols and units are upright strings,
+//! math in `$...$` and commands like `\alpha` go through [`latex_to_typst`].
+
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use super::{ParseError, latex_to_typst};
+use crate::typ;
+
+/// Reaction arrows and their Typst symbols, longest first so `<=>>` is not taken as `<=>`
+const ARROWS: &[(&str, &str)] = &[
+	("<=>>", "harpoons.rtlb"),
+	("<<=>", "harpoons.rtlb"),
+	("<-->", "arrows.rl"),
+	("<=>", "harpoons.rtlb"),
+	("<->", "<->"),
+	("->", "->"),
+	("<-", "<-"),
+];
+/// Number with an optional exponent at the start of `\pu`, `1.5e-3` or `6.02*10^23`
+static QUANTITY: LazyLock<Regex> = LazyLock::new(|| {
+	Regex::new(r"^([+-]?\d+(?:[.,]\d+)?)(?:\s*(?:[eE]|[*x]\s*10\^|\\times\s*10\^)\s*\{?([+-]?\d+)\}?)?\s*").unwrap()
+});
+
+/// Convert a chemical equation, species and arrows are separated by whitespace.
+pub fn ce_to_typst(ce: &str) -> Result<String, ParseError> {
+	let mut items = Vec::new();
+	let mut rest = ce.trim_start();
+	while !rest.is_empty() {
+		if let Some(&(arrow, symbol)) = ARROWS.iter().find(|(arrow, _)| rest.starts_with(arrow)) {
+			rest = &rest[arrow.len()..];
+			// conditions above and below the arrow
+			let above = bracket(&mut rest).filter(|above| !above.trim().is_empty());
+			let below = bracket(&mut rest).filter(|below| !below.trim().is_empty());
+			let mut item = match (above, below) {
+				(None, None) => symbol.to_string(),
+				_ => format!("stretch({symbol})"),
+			};
+			if let Some(above) = above {
+				item += &format!("^({})", ce_to_typst(above)?);
+			}
+			if let Some(below) = below {
+				item += &format!("_({})", ce_to_typst(below)?);
+			}
+			items.push(item);
+		} else {
+			let end = word_end(rest);
+			items.push(match &rest[..end] {
+				"+" => "+".into(),
+				"=" => "=".into(),
+				"*" | "." => "dot.op".into(),
+				// precipitate and gas
+				"v" => "arrow.b".into(),
+				"^" => "arrow.t".into(),
+				word => formula(word)?,
+			});
+			rest = &rest[end..];
+		}
+		rest = rest.trim_start();
+	}
+	Ok(items.join(" "))
+}
+
+/// Convert a physical quantity, a number followed by units like `kJ mol-1` or `m/s^2`.
+pub fn pu_to_typst(pu: &str) -> Result<String, ParseError> {
+	let pu = pu.trim();
+	let (number, units) = match QUANTITY.captures(pu) {
+		Some(c) => {
+			let number = match c.get(2) {
+				Some(exponent) => format!("{} times 10^{}", &c[1], script(exponent.as_str())),
+				None => c[1].to_string(),
+			};
+			(number, &pu[c.get(0).unwrap().end()..])
+		}
+		None => (String::new(), pu),
+	};
+
+	let chars: Vec<char> = units.chars().collect();
+	let mut ret = String::new();
+	let mut i = 0;
+	while i < chars.len() {
+		let c = chars[i];
+		i += 1;
+		match c {
+			' ' => ret += " space.thin ",
+			'.' | '*' => ret += " dot.op ",
+			'/' => ret += " \\/ ",
+			'^' => {
+				let (exponent, end) = script_arg(&chars, i, |c| c.is_ascii_digit() || c == '-' || c == '+');
+				i = end;
+				ret += &format!("^{}", script(&exponent));
+			}
+			'\\' => {
+				let (command, end) = command(&chars, i - 1);
+				i = end;
+				ret += &format!("upright({})", latex_to_typst(command.into())?);
+			}
+			// exponents without `^`, `mol-1` or `m2`
+			'-' | '0'..='9' => {
+				let end = (i..chars.len())
+					.find(|&j| !chars[j].is_ascii_digit())
+					.unwrap_or(chars.len());
+				ret += &format!("^{}", script(&chars[i - 1..end].iter().collect::<String>()));
+				i = end;
+			}
+			c if c.is_alphabetic() || "°%'\"".contains(c) => {
+				let end = (i..chars.len())
+					.find(|&j| !(chars[j].is_alphabetic() || "°%".contains(chars[j])))
+					.unwrap_or(chars.len());
+				let unit: String = chars[i - 1..end].iter().collect();
+				ret += &format!("\"{}\"", typ::escape_string(&unit));
+				i = end;
+			}
+			c => ret.push(c),
+		}
+	}
+	Ok(match (number.is_empty(), ret.is_empty()) {
+		(_, true) => number,
+		(true, false) => ret,
+		(false, false) => format!("{number} space.thin {ret}"),
+	})
+}
+
+/// Convert a single species like `2H2O`, `SO4^2-`, `Fe(OH)3(s)` or `^{14}_{6}C`.
+fn formula(word: &str) -> Result<String, ParseError> {
+	let chars: Vec<char> = word.chars().collect();
+	let mut ret = String::new();
+	// letters of element symbols not written out yet, so `NaCl` is a single string
+	let mut symbols = String::new();
+	let flush = |ret: &mut String, symbols: &mut String| {
+		if !symbols.is_empty() {
+			*ret += &format!("\"{symbols}\"");
+			symbols.clear();
+		}
+	};
+	// a coefficient can follow, at the start and after `*` in adducts like `CuSO4*5H2O`
+	let mut start = true;
+	let mut i = 0;
+	while i < chars.len() {
+		let c = chars[i];
+		if start {
+			start = false;
+			// stoichiometric coefficient, `2` or `1/2`
+			let end = (i..chars.len())
+				.find(|&j| !(chars[j].is_ascii_digit() || chars[j] == '/' || chars[j] == '.'))
+				.unwrap_or(chars.len());
+			if end > i {
+				ret += &chars[i..end].iter().collect::<String>();
+				i = end;
+				continue;
+			}
+			// isotope with mass and atomic numbers before the symbol
+			if (c == '^' || c == '_')
+				&& let Some(isotope) = isotope(&chars, &mut i)?
+			{
+				ret += &isotope;
+				continue;
+			}
+		}
+		i += 1;
+		match c {
+			'A'..='Z' => {
+				symbols.push(c);
+				while i < chars.len() && chars[i].is_ascii_lowercase() {
+					symbols.push(chars[i]);
+					i += 1;
+				}
+			}
+			// a variable like `n` in `nH2O`, or text like `heat` in arrow conditions
+			'a'..='z' => {
+				flush(&mut ret, &mut symbols);
+				let end = (i..chars.len())
+					.find(|&j| !chars[j].is_ascii_lowercase())
+					.unwrap_or(chars.len());
+				match end - i {
+					0 => ret.push(c),
+					_ => ret += &format!("\"{}\"", chars[i - 1..end].iter().collect::<String>()),
+				}
+				i = end;
+			}
+			'0'..='9' => {
+				flush(&mut ret, &mut symbols);
+				let end = (i..chars.len())
+					.find(|&j| !(chars[j].is_ascii_digit() || chars[j] == '.'))
+					.unwrap_or(chars.len());
+				let digits: String = chars[i - 1..end].iter().collect();
+				i = end;
+				// `Ca2+` is a charge, `H2` a count
+				match charge_end(&chars, i) {
+					Some(end) => {
+						ret += &format!("^({digits}{})", chars[i..end].iter().collect::<String>());
+						i = end;
+					}
+					None => ret += &format!("_{}", script(&digits)),
+				}
+			}
+			'+' | '-' => {
+				flush(&mut ret, &mut symbols);
+				match charge_end(&chars, i - 1) {
+					Some(end) => {
+						ret += &format!("^{}", script(&chars[i - 1..end].iter().collect::<String>()));
+						i = end;
+					}
+					// bond
+					None if c == '-' => ret += "\"-\"",
+					None => ret.push(c),
+				}
+			}
+			'=' => {
+				flush(&mut ret, &mut symbols);
+				ret.push('=');
+			}
+			'#' => {
+				flush(&mut ret, &mut symbols);
+				ret += "equiv";
+			}
+			'*' | '.' => {
+				flush(&mut ret, &mut symbols);
+				ret += " dot.op ";
+				start = true;
+			}
+			'(' => {
+				flush(&mut ret, &mut symbols);
+				// state like `(aq)` or `(s)`
+				match (i..chars.len()).find(|&j| !chars[j].is_ascii_lowercase()) {
+					Some(end) if end > i && chars[end] == ')' => {
+						ret += &format!(" \"({})\"", chars[i..end].iter().collect::<String>());
+						i = end + 1;
+					}
+					_ => ret.push(c),
+				}
+			}
+			'^' | '_' => {
+				flush(&mut ret, &mut symbols);
+				let (arg, end) = script_arg(&chars, i, |c| c.is_ascii_digit() || c == '+' || c == '-');
+				i = end;
+				let arg = match arg.as_str() {
+					// radical
+					"." => "dot.c".into(),
+					arg => latex_to_typst(arg.into())?.into_owned(),
+				};
+				ret += &format!("{c}{}", script(&arg));
+			}
+			'$' => {
+				flush(&mut ret, &mut symbols);
+				let end = (i..chars.len()).find(|&j| chars[j] == '$').unwrap_or(chars.len());
+				ret += &latex_to_typst(chars[i..end].iter().collect::<String>().into())?;
+				i = end + 1;
+			}
+			'\\' => {
+				flush(&mut ret, &mut symbols);
+				let (command, end) = command(&chars, i - 1);
+				i = end;
+				ret += &latex_to_typst(command.into())?;
+			}
+			'{' => {
+				flush(&mut ret, &mut symbols);
+				let (group, end) = group(&chars, i);
+				i = end;
+				ret += &formula(&group)?;
+			}
+			c => {
+				flush(&mut ret, &mut symbols);
+				ret.push(c);
+			}
+		}
+	}
+	flush(&mut ret, &mut symbols);
+	Ok(ret)
+}
+
+/// `^{14}_{6}C` as `attach("C", tl: 14, bl: 6)`, `i` is at the first script.
+fn isotope(chars: &[char], i: &mut usize) -> Result<Option<String>, ParseError> {
+	let mut j = *i;
+	let mut scripts = Vec::new();
+	while j < chars.len() && (chars[j] == '^' || chars[j] == '_') {
+		let (arg, end) = script_arg(chars, j + 1, |c| c.is_ascii_digit());
+		scripts.push(format!(
+			"{}: {}",
+			if chars[j] == '^' { "tl" } else { "bl" },
+			latex_to_typst(arg.into())?
+		));
+		j = end;
+	}
+	if j >= chars.len() || !chars[j].is_ascii_uppercase() {
+		return Ok(None);
+	}
+	let end = (j + 1..chars.len())
+		.find(|&k| !chars[k].is_ascii_lowercase())
+		.unwrap_or(chars.len());
+	let symbol: String = chars[j..end].iter().collect();
+	*i = end;
+	Ok(Some(format!("attach(\"{symbol}\", {})", scripts.join(", "))))
+}
+
+/// End of a charge starting at `i`, signs at the end of the species or before its state.
+fn charge_end(chars: &[char], i: usize) -> Option<usize> {
+	let end = (i..chars.len())
+		.find(|&j| chars[j] != '+' && chars[j] != '-')
+		.unwrap_or(chars.len());
+	(end > i && (end == chars.len() || chars[end] == '(')).then_some(end)
+}
+
+/// Argument of a script at `i`, a group or characters while `f` holds (at least one).
+fn script_arg(chars: &[char], i: usize, f: impl Fn(char) -> bool) -> (String, usize) {
+	match chars.get(i) {
+		Some('{') => group(chars, i + 1),
+		Some(_) => {
+			let end = (i + 1..chars.len()).find(|&j| !f(chars[j])).unwrap_or(chars.len());
+			(chars[i..end].iter().collect(), end)
+		}
+		None => (String::new(), i),
+	}
+}
+
+/// Content of a group until the matching `}`, `i` is after the opening `{`.
+fn group(chars: &[char], i: usize) -> (String, usize) {
+	let mut depth = 0;
+	for j in i..chars.len() {
+		match chars[j] {
+			'{' => depth += 1,
+			'}' if depth == 0 => return (chars[i..j].iter().collect(), j + 1),
+			'}' => depth -= 1,
+			_ => {}
+		}
+	}
+	(chars[i..].iter().collect(), chars.len())
+}
+
+/// A LaTeX command at `i` with its braced arguments, like `\text{heat}`.
+fn command(chars: &[char], i: usize) -> (String, usize) {
+	let mut end = (i + 1..chars.len())
+		.find(|&j| !chars[j].is_ascii_alphabetic())
+		.unwrap_or(chars.len());
+	if end == i + 1 {
+		// control symbol
+		end = (end + 1).min(chars.len());
+	}
+	while chars.get(end) == Some(&'{') {
+		end = group(chars, end + 1).1;
+	}
+	(chars[i..end].iter().collect(), end)
+}
+
+/// Script content, parenthesized unless it is a single number or character.
+fn script(content: &str) -> String {
+	if content.chars().count() == 1 || content.chars().all(|c| c.is_ascii_digit()) {
+		content.to_string()
+	} else {
+		format!("({content})")
+	}
+}
+
+/// Content of `[...]` at the start of `rest`, which is advanced past it.
+fn bracket<'a>(rest: &mut &'a str) -> Option<&'a str> {
+	let inner = rest.strip_prefix('[')?;
+	let mut depth = 0;
+	for (i, c) in inner.char_indices() {
+		match c {
+			'[' => depth += 1,
+			']' if depth == 0 => {
+				*rest = &inner[i + 1..];
+				return Some(&inner[..i]);
+			}
+			']' => depth -= 1,
+			_ => {}
+		}
+	}
+	None
+}
+
+/// End of a species, at whitespace or an arrow outside of groups and math.
+fn word_end(rest: &str) -> usize {
+	let mut depth = 0;
+	let mut math = false;
+	for (i, c) in rest.char_indices() {
+		match c {
+			'{' => depth += 1,
+			'}' => depth -= 1,
+			'$' => math = !math,
+			c if depth == 0
+				&& !math && (c.is_whitespace() || i > 0 && ARROWS.iter().any(|(a, _)| rest[i..].starts_with(a))) =>
+			{
+				return i;
+			}
+			_ => {}
+		}
+	}
+	rest.len()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_ce() {
+		assert_eq!(
+			ce_to_typst("2H2 + O2 -> 2H2O").unwrap(),
+			"2\"H\"_2 + \"O\"_2 -> 2\"H\"_2\"O\""
+		);
+		assert_eq!(
+			ce_to_typst("SO4^2- + Ca^{2+} <=> CaSO4 v").unwrap(),
+			"\"SO\"_4^(2-) + \"Ca\"^(2+) harpoons.rtlb \"CaSO\"_4 arrow.b"
+		);
+		assert_eq!(ce_to_typst("Fe3+ + OH-").unwrap(), "\"Fe\"^(3+) + \"OH\"^-");
+		assert_eq!(
+			ce_to_typst("NaCl(aq) + Ca(OH)2(s)").unwrap(),
+			"\"NaCl\" \"(aq)\" + \"Ca\"(\"OH\")_2 \"(s)\""
+		);
+		assert_eq!(
+			ce_to_typst("CaCO3 ->[heat][$\\Delta$] CaO + CO2 ^").unwrap(),
+			"\"CaCO\"_3 stretch(->)^(\"heat\")_(Delta) \"CaO\" + \"CO\"_2 arrow.t"
+		);
+		assert_eq!(ce_to_typst("CuSO4*5H2O").unwrap(), "\"CuSO\"_4 dot.op 5\"H\"_2\"O\"");
+		assert_eq!(ce_to_typst("^{14}_{6}C").unwrap(), "attach(\"C\", tl: 14, bl: 6)");
+		assert_eq!(ce_to_typst("CH3-CH=CH2").unwrap(), "\"CH\"_3\"-\"\"CH\"=\"CH\"_2");
+		// the argument is not parsed as math
+		assert_eq!(
+			latex_to_typst("x = \\ce {CO2 ^} + \\pu{5 g}".into()).unwrap(),
+			"x = \"CO\"_2 arrow.t + 5 space.thin \"g\""
+		);
+	}
+
+	#[test]
+	fn test_pu() {
+		assert_eq!(pu_to_typst("123 kJ/mol").unwrap(), "123 space.thin \"kJ\" \\/ \"mol\"");
+		assert_eq!(
+			pu_to_typst("1.2e-3 J mol-1 K^-1").unwrap(),
+			"1.2 times 10^(-3) space.thin \"J\" space.thin \"mol\"^(-1) space.thin \"K\"^(-1)"
+		);
+		assert_eq!(pu_to_typst("m/s^2").unwrap(), "\"m\" \\/ \"s\"^2");
+	}
+}
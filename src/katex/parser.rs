@@ -0,0 +1,295 @@
+//! Tokenizer and parser of LaTeX math. The tree keeps byte ranges into the source, so arguments can be taken raw
+//! (colors, text) and errors point at the offending command.
+
+use std::{iter::Peekable, ops::Range, vec::IntoIter};
+
+use super::ParseError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token<'a> {
+	/// `\name`, or `\` followed by a single other character like `\,`
+	Command(&'a str),
+	Char(char),
+	BeginGroup,
+	EndGroup,
+	Sub,
+	Sup,
+	/// `% comment` without the line break
+	Comment(&'a str),
+}
+
+fn tokenize(src: &str) -> Result<Vec<(Token<'_>, Range<usize>)>, ParseError> {
+	let mut tokens = Vec::new();
+	let mut chars = src.char_indices().peekable();
+	while let Some((start, c)) = chars.next() {
+		let token = match c {
+			'\\' => {
+				let name_start = start + 1;
+				let mut end = name_start;
+				while let Some(&(i, c)) = chars.peek()
+					&& c.is_ascii_alphabetic()
+				{
+					chars.next();
+					end = i + 1;
+				}
+				if end == name_start {
+					// control symbol
+					let (i, c) = chars
+						.next()
+						.ok_or_else(|| ParseError::unexpected_eof("control sequence", start))?;
+					end = i + c.len_utf8();
+				} else if &src[name_start..end] == "operatorname"
+					&& let Some(&(i, '*')) = chars.peek()
+				{
+					chars.next();
+					end = i + 1;
+				}
+				Token::Command(&src[name_start..end])
+			}
+			'{' => Token::BeginGroup,
+			'}' => Token::EndGroup,
+			'_' => Token::Sub,
+			'^' => Token::Sup,
+			'%' => {
+				let end = chars.by_ref().find(|&(_, c)| c == '\n').map_or(src.len(), |(i, _)| i);
+				tokens.push((Token::Comment(&src[start + 1..end]), start..end));
+				continue;
+			}
+			c => Token::Char(c),
+		};
+		let end = chars.peek().map_or(src.len(), |&(i, _)| i);
+		tokens.push((token, start..end));
+	}
+	Ok(tokens)
+}
+
+/// A node of LaTeX math, `span` is its byte range in the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node<'a> {
+	pub kind: Kind<'a>,
+	pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Kind<'a> {
+	Char(char),
+	/// Command name without `\`, arguments are the following nodes as its arity is only known when converting
+	Command(&'a str),
+	/// `{...}`
+	Group(Vec<Node<'a>>),
+	/// `_x` or `_{...}`
+	Sub(Box<Node<'a>>),
+	/// `^x` or `^{...}`
+	Sup(Box<Node<'a>>),
+	/// `\begin{name}...\end{name}`, arguments like the column spec are the first nodes of `body`
+	Environment {
+		name: &'a str,
+		body: Vec<Node<'a>>,
+	},
+	/// `\left(...\right)`
+	LeftRight {
+		left: Box<Node<'a>>,
+		body: Vec<Node<'a>>,
+		right: Box<Node<'a>>,
+	},
+	Comment(&'a str),
+}
+
+impl Node<'_> {
+	pub fn is_whitespace(&self) -> bool {
+		matches!(self.kind, Kind::Char(c) if c.is_whitespace())
+	}
+}
+
+/// Source covered by consecutive nodes.
+pub fn raw<'a>(src: &'a str, nodes: &[Node]) -> &'a str {
+	match nodes {
+		[] => "",
+		[
+			Node {
+				kind: Kind::Group(_),
+				span,
+			},
+		] => &src[span.start + 1..span.end - 1],
+		[first, .., last] | [first @ last] => &src[first.span.start..last.span.end],
+	}
+}
+
+/// Parse LaTeX math into a tree.
+pub fn parse(src: &str) -> Result<Vec<Node<'_>>, ParseError> {
+	let mut parser = Parser {
+		src,
+		tokens: tokenize(src)?.into_iter().peekable(),
+	};
+	match parser.nodes()? {
+		(nodes, None) => Ok(nodes),
+		(_, Some((_, span))) => Err(ParseError::unmatched(&src[span.clone()], span.start)),
+	}
+}
+
+struct Parser<'a> {
+	src: &'a str,
+	tokens: Peekable<IntoIter<(Token<'a>, Range<usize>)>>,
+}
+
+type Terminator<'a> = Option<(Token<'a>, Range<usize>)>;
+
+impl<'a> Parser<'a> {
+	/// Nodes until the end of input or a terminator (`}`, `\end` or `\right`), which is returned to the caller.
+	fn nodes(&mut self) -> Result<(Vec<Node<'a>>, Terminator<'a>), ParseError> {
+		let mut nodes = Vec::new();
+		while let Some((token, span)) = self.tokens.next() {
+			if matches!(token, Token::EndGroup | Token::Command("end" | "right")) {
+				return Ok((nodes, Some((token, span))));
+			}
+			let node = self.node(token, span)?;
+			let verbatim = matches!(node.kind, Kind::Command("ce" | "pu"));
+			nodes.push(node);
+			// mhchem has its own syntax, like `^` alone for a gas
+			if verbatim {
+				self.skip_whitespace();
+				if let Some((Token::BeginGroup, start)) = self.tokens.next_if(|(token, _)| *token == Token::BeginGroup)
+				{
+					nodes.push(self.verbatim(start)?);
+				}
+			}
+		}
+		Ok((nodes, None))
+	}
+
+	/// A group kept as source, its span covers it and it has no children.
+	fn verbatim(&mut self, start: Range<usize>) -> Result<Node<'a>, ParseError> {
+		let mut depth = 0;
+		for (token, span) in self.tokens.by_ref() {
+			match token {
+				Token::BeginGroup => depth += 1,
+				Token::EndGroup if depth == 0 => {
+					return Ok(Node {
+						kind: Kind::Group(Vec::new()),
+						span: start.start..span.end,
+					});
+				}
+				Token::EndGroup => depth -= 1,
+				_ => {}
+			}
+		}
+		Err(ParseError::unmatched("{", start.start))
+	}
+
+	fn skip_whitespace(&mut self) {
+		while let Some((Token::Char(c), _)) = self.tokens.peek()
+			&& c.is_whitespace()
+		{
+			self.tokens.next();
+		}
+	}
+
+	fn node(&mut self, token: Token<'a>, span: Range<usize>) -> Result<Node<'a>, ParseError> {
+		let (kind, end) = match token {
+			Token::Char(c) => (Kind::Char(c), span.end),
+			Token::Comment(text) => (Kind::Comment(text), span.end),
+			Token::Command("begin") => {
+				let (name, _) = self.name(span.start)?;
+				let (body, end) = self.nodes()?;
+				let end = match end {
+					Some((Token::Command("end"), end)) => match self.name(end.start)? {
+						(end_name, end) if end_name == name => end,
+						_ => return Err(ParseError::unmatched(format!("\\begin{{{name}}}"), span.start)),
+					},
+					_ => return Err(ParseError::unmatched(format!("\\begin{{{name}}}"), span.start)),
+				};
+				(Kind::Environment { name, body }, end)
+			}
+			Token::Command("left") => {
+				let left = self.argument(span.start)?;
+				let (body, end) = self.nodes()?;
+				if !matches!(end, Some((Token::Command("right"), _))) {
+					return Err(ParseError::unmatched("\\left", span.start));
+				}
+				let right = self.argument(span.start)?;
+				let end = right.span.end;
+				(
+					Kind::LeftRight {
+						left: Box::new(left),
+						body,
+						right: Box::new(right),
+					},
+					end,
+				)
+			}
+			Token::Command(name) => (Kind::Command(name), span.end),
+			Token::BeginGroup => match self.nodes()? {
+				(nodes, Some((Token::EndGroup, end))) => (Kind::Group(nodes), end.end),
+				_ => return Err(ParseError::unmatched("{", span.start)),
+			},
+			Token::Sub | Token::Sup => {
+				let arg = Box::new(self.argument(span.start)?);
+				let end = arg.span.end;
+				match token {
+					Token::Sub => (Kind::Sub(arg), end),
+					_ => (Kind::Sup(arg), end),
+				}
+			}
+			Token::EndGroup => unreachable!(),
+		};
+		Ok(Node {
+			kind,
+			span: span.start..end,
+		})
+	}
+
+	/// The next node after whitespace, for scripts and delimiters.
+	fn argument(&mut self, offset: usize) -> Result<Node<'a>, ParseError> {
+		self.skip_whitespace();
+		match self.tokens.next() {
+			Some((Token::EndGroup | Token::Command("end" | "right"), _)) | None => {
+				Err(ParseError::unexpected_eof("argument", offset))
+			}
+			Some((token, span)) => self.node(token, span),
+		}
+	}
+
+	/// Environment name in `\begin{name}` or `\end{name}`, with the end offset.
+	fn name(&mut self, offset: usize) -> Result<(&'a str, usize), ParseError> {
+		self.skip_whitespace();
+		let Some((Token::BeginGroup, start)) = self.tokens.next() else {
+			return Err(ParseError::unexpected_eof("environment name", offset));
+		};
+		for (token, span) in self.tokens.by_ref() {
+			if token == Token::EndGroup {
+				return Ok((self.src[start.end..span.start].trim(), span.end));
+			}
+		}
+		Err(ParseError::unexpected_eof("environment name", offset))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse() {
+		let nodes = parse("\\frac{a}b_{1}^\\prime \\begin{matrix}x\\end{matrix}").unwrap();
+		let kinds: Vec<_> = nodes.iter().map(|node| &node.kind).collect();
+		assert!(matches!(kinds[0], Kind::Command("frac")));
+		assert!(matches!(kinds[1], Kind::Group(nodes) if nodes.len() == 1));
+		assert!(matches!(kinds[2], Kind::Char('b')));
+		assert!(matches!(kinds[3], Kind::Sub(node) if matches!(node.kind, Kind::Group(_))));
+		assert!(matches!(kinds[4], Kind::Sup(node) if node.kind == Kind::Command("prime")));
+		assert!(matches!(kinds[6], Kind::Environment { name: "matrix", body } if body.len() == 1));
+		assert_eq!(raw("\\frac{a}b", &nodes[1..2]), "a");
+		assert_eq!(nodes[6].span, 21..48);
+
+		assert!(matches!(
+			parse("x + \\frac{1}{2"),
+			Err(ParseError::Unmatched { offset: 12, .. })
+		));
+		assert!(matches!(
+			parse("\\left( x"),
+			Err(ParseError::Unmatched { offset: 0, .. })
+		));
+		assert!(parse("a}").is_err());
+		assert!(parse("\\begin{matrix}x\\end{pmatrix}").is_err());
+	}
+}
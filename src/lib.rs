@@ -0,0 +1,50 @@
+pub mod body;
+mod katex;
+mod md;
+mod typ;
+
+pub use katex::{take_latex_errors, take_unknown_commands};
+
+use std::{path::PathBuf, str::FromStr, sync::OnceLock};
+
+// settings shared by all conversions, the state of the notebook being converted is kept per thread in each module, so
+// notebooks can be converted in parallel threads
+pub static IMG_PATH: OnceLock<String> = OnceLock::new();
+/// Prefer vector outputs (PDF) over raster ones (PNG) in code outputs
+pub static PREFER_VECTOR: OnceLock<bool> = OnceLock::new();
+/// Absolute paths of bibliography files for citations
+pub static BIBLIOGRAPHY: OnceLock<Vec<PathBuf>> = OnceLock::new();
+/// Wrap images alone in a paragraph in figures, captioned with their title or alt text
+pub static FIGURES: OnceLock<bool> = OnceLock::new();
+/// LaTeX macro definitions from `--macros`, available in all notebooks
+pub static MACROS: OnceLock<String> = OnceLock::new();
+/// Which block equations are numbered, from `--numbering`
+pub static NUMBERING: OnceLock<Numbering> = OnceLock::new();
+
+/// Numbering of block equations. Equations with `\label` or `\tag` are always numbered, so they can be referenced.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Numbering {
+	/// Only equations with `\label` or `\tag`
+	#[default]
+	Labeled,
+	/// Numbered environments like `equation` and `align` as a whole, unless `\notag`
+	Equation,
+	/// Numbered environments, with each row of `align` and `gather` numbered separately. Rows become separate
+	/// equations, so `&` no longer aligns them with each other
+	Row,
+}
+
+impl FromStr for Numbering {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"labeled" => Ok(Self::Labeled),
+			"equation" => Ok(Self::Equation),
+			"row" => Ok(Self::Row),
+			_ => Err(format!(
+				"Unknown numbering `{s}`, expected `labeled`, `equation` or `row`"
+			)),
+		}
+	}
+}
@@ -1,15 +1,16 @@
-mod body;
-mod katex;
-mod md;
-mod typ;
-
-use serde_json::Value;
+use itertools::Itertools;
+use jupyter2typst::{
+	BIBLIOGRAPHY, FIGURES, IMG_PATH, MACROS, NUMBERING, Numbering, PREFER_VECTOR, body, take_latex_errors,
+	take_unknown_commands,
+};
+use serde_json::{Value, json};
 use std::{
+	collections::BTreeMap,
 	env,
 	fs::{self, File},
 	io::{Read, Write},
-	path::Path,
-	sync::OnceLock,
+	path::{Path, PathBuf},
+	process,
 };
 
 use argh::FromArgs;
@@ -17,51 +18,171 @@ use argh::FromArgs;
 #[derive(FromArgs)]
 #[argh(description = "A simple tool to convert a Jupyter Notebook to Typst format")]
 struct Args {
-	/// the input file
+	/// the input files, links between them are resolved
 	#[argh(positional)]
-	input: String,
+	input: Vec<String>,
 
-	/// the output file path (without extension)
+	/// the output file path (without extension), only for a single input file
 	#[argh(option, short = 'o')]
 	output: Option<String>,
 
 	/// the output image path, defaults to "img"
 	#[argh(option, short = 'i', default = "String::from(\"img\")")]
 	img_path: String,
-}
 
-static IMG_PATH: OnceLock<String> = OnceLock::new();
+	/// prefer PDF over PNG in code outputs, PDF is included with `pdf-image` in the template
+	#[argh(switch)]
+	prefer_vector: bool,
+
+	/// wrap images alone in a paragraph in figures, captioned with their title or alt text
+	#[argh(switch)]
+	figures: bool,
+
+	/// bibliography file for citations, added with `#bibliography` at the end
+	#[argh(option)]
+	bibliography: Vec<String>,
+
+	/// file with LaTeX macro definitions (`\newcommand`, `\def`) available in all notebooks
+	#[argh(option)]
+	macros: Option<String>,
+
+	/// which block equations are numbered: `labeled` (only with `\label` or `\tag`, the default), `equation` or
+	/// `row` (each row of `align` and `gather` as a separate equation, which drops the alignment between rows)
+	#[argh(option, default = "Numbering::Labeled")]
+	numbering: Numbering,
+
+	/// exit with an error after writing the outputs if LaTeX math can't be converted, it is kept as source either way,
+	/// for CI
+	#[argh(switch)]
+	strict: bool,
+
+	/// write unknown LaTeX commands with their counts and cells as JSON to this file, they are always summarized on
+	/// stderr
+	#[argh(option)]
+	unknown_commands: Option<String>,
+}
 
 fn main() {
 	let args: Args = argh::from_env();
 	IMG_PATH.set(args.img_path).unwrap();
+	PREFER_VECTOR.set(args.prefer_vector).unwrap();
+	FIGURES.set(args.figures).unwrap();
+	BIBLIOGRAPHY
+		.set(
+			args.bibliography
+				.iter()
+				.map(|file| fs::canonicalize(file).expect("Failed to find bibliography file"))
+				.collect(),
+		)
+		.unwrap();
+	MACROS
+		.set(
+			args.macros
+				.map(|file| fs::read_to_string(file).expect("Failed to read macros file"))
+				.unwrap_or_default(),
+		)
+		.unwrap();
+	NUMBERING.set(args.numbering).unwrap();
+	if args.input.is_empty() {
+		panic!("No input file");
+	}
+	if args.output.is_some() && args.input.len() > 1 {
+		panic!("Output path can only be used with a single input file");
+	}
+
+	let current_dir = env::current_dir().expect("Failed to get current directory");
+	let notebooks: Vec<(PathBuf, Value)> = args
+		.input
+		.iter()
+		.map(|input| {
+			let extension = input.split('.').next_back().expect("Failed to get file extension");
+			let json: Value = match extension {
+				"ipynb" => {
+					let mut file = File::open(input).expect("Failed to open file");
+					let mut contents = String::new();
+					file.read_to_string(&mut contents).expect("Failed to read file");
+					serde_json::from_str(&contents).expect("Failed to parse JSON")
+				}
+				_ => panic!("Invalid file extension"),
+			};
+			(current_dir.join(input), json)
+		})
+		.collect();
+	// links between notebooks in the same batch are resolved to their labels
+	for (path, json) in &notebooks {
+		body::ipynb_register(path, json);
+	}
+
+	let mut latex_errors = 0;
+	// <command, (notebook, cell) of each use>
+	let mut unknown: BTreeMap<String, Vec<(String, usize)>> = BTreeMap::new();
+	for ((abs_path, json), input) in notebooks.into_iter().zip(&args.input) {
+		env::set_current_dir(abs_path.parent().unwrap()).expect("Failed to set current directory");
+		fs::create_dir_all(IMG_PATH.get().unwrap()).expect("Failed to create image directory");
+		let out_file = args
+			.output
+			.clone()
+			.unwrap_or_else(|| abs_path.file_stem().unwrap().to_str().unwrap().to_string());
+		let out_file = format!("{out_file}.typ");
+		let output = body::ipynb_parse(json, Path::new(&out_file));
 
-	let extension = args.input.split('.').next_back().expect("Failed to get file extension");
-	let json: Value = match extension {
-		"ipynb" => {
-			let mut file = File::open(&args.input).expect("Failed to open file");
-			let mut contents = String::new();
-			file.read_to_string(&mut contents).expect("Failed to read file");
-			serde_json::from_str(&contents).expect("Failed to parse JSON")
+		let mut file = File::create(&out_file).expect("Failed to create/open file");
+		file.write_all(output.as_bytes()).expect("Failed to write file");
+		for (cell, error) in take_latex_errors() {
+			eprintln!("Failed to convert LaTeX in {input} cell {cell}: {error}, kept as source");
+			latex_errors += 1;
 		}
-		_ => panic!("Invalid file extension"),
-	};
-
-	let abs_path = env::current_dir()
-		.expect("Failed to get current directory")
-		.join(&args.input);
-	env::set_current_dir(abs_path.parent().unwrap()).expect("Failed to set current directory");
-	fs::create_dir_all(IMG_PATH.get().unwrap()).expect("Failed to create image directory");
-	let output = body::ipynb_parse(json);
-
-	let out_file = args.output.unwrap_or_else(|| {
-		Path::new(&args.input)
-			.file_stem()
-			.unwrap()
-			.to_str()
-			.unwrap()
-			.to_string()
-	});
-	let mut file = File::create(format!("{out_file}.typ")).expect("Failed to create/open file");
-	file.write_all(output.as_bytes()).expect("Failed to write file");
+		for (command, cells) in take_unknown_commands() {
+			unknown
+				.entry(command)
+				.or_default()
+				.extend(cells.into_iter().map(|cell| (input.clone(), cell)));
+		}
+	}
+
+	// most used first
+	let unknown = unknown
+		.into_iter()
+		.sorted_by(|(_, a), (_, b)| b.len().cmp(&a.len()))
+		.collect_vec();
+	if !unknown.is_empty() {
+		eprintln!("Unknown LaTeX commands, emitted as is:");
+		for (command, uses) in &unknown {
+			let locations = uses
+				.iter()
+				.chunk_by(|(notebook, _)| notebook)
+				.into_iter()
+				.map(|(notebook, uses)| {
+					let cells = uses.map(|(_, cell)| cell).dedup().collect_vec();
+					let noun = if cells.len() == 1 { "cell" } else { "cells" };
+					format!("{notebook} {noun} {}", cells.iter().join(", "))
+				})
+				.join("; ");
+			eprintln!("  \\{command} ({}): {locations}", uses.len());
+		}
+	}
+	if let Some(path) = args.unknown_commands {
+		let report: Vec<Value> = unknown
+			.iter()
+			.map(|(command, uses)| {
+				json!({
+					"command": format!("\\{command}"),
+					"count": uses.len(),
+					"locations": uses
+						.iter()
+						.dedup()
+						.map(|(notebook, cell)| json!({ "notebook": notebook, "cell": cell }))
+						.collect_vec(),
+				})
+			})
+			.collect();
+		// relative to where the tool was run
+		fs::write(current_dir.join(path), serde_json::to_string_pretty(&report).unwrap())
+			.expect("Failed to write unknown commands report");
+	}
+
+	if args.strict && latex_errors > 0 {
+		eprintln!("Stopped by --strict, {latex_errors} LaTeX expression(s) could not be converted");
+		process::exit(1);
+	}
 }
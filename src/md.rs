@@ -1,19 +1,106 @@
 use itertools::Itertools;
-use markdown::{Constructs, ParseOptions, mdast::Node, to_mdast};
+use markdown::{
+	Constructs, ParseOptions,
+	mdast::{AlignKind, Blockquote, Code, Definition, Heading, Image, Link, Node, Paragraph, Table, Text},
+	to_mdast,
+};
+use regex::{Captures, Regex};
 use reqwest::blocking;
 use sha1::{Digest, Sha1};
 use std::borrow::Cow;
+use std::cell::RefCell;
 use std::fmt::Write as _;
 use std::sync::{LazyLock, Mutex};
-use std::{collections::HashMap, fs::File, io::Write};
+use std::{
+	collections::{HashMap, HashSet},
+	fs::{self, File},
+	io::Write,
+	path::{Path, PathBuf},
+};
 use url::Url;
 
-use crate::IMG_PATH;
+use crate::{BIBLIOGRAPHY, FIGURES, IMG_PATH};
 use crate::{katex, typ};
 
-static FOOTNOTE_DEFINITIONS: LazyLock<Mutex<HashMap<String, String>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
-/// <name in attachments, file path>
-static ATTACHMENTS: LazyLock<Mutex<HashMap<String, String>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+static FOOTNOTE_REFERENCE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[\^([^\]]+)\]").unwrap());
+/// GitHub alert marker in the first line of a blockquote, `> [!NOTE]`
+static ALERT: LazyLock<Regex> =
+	LazyLock::new(|| Regex::new(r"^\[!(?i)(note|tip|important|warning|caution)\][ \t]*([^\n]*)\n?").unwrap());
+/// MyST colon fence opening, `:::{note}`
+static COLON_FENCE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^ {0,3}(:{3,})\{([\w-]+)\}(.*)$").unwrap());
+/// MyST directives converted to admonitions
+const ADMONITIONS: &[&str] = &[
+	"admonition",
+	"attention",
+	"caution",
+	"danger",
+	"error",
+	"hint",
+	"important",
+	"note",
+	"seealso",
+	"tip",
+	"warning",
+];
+/// Pandoc citation group, `[see @key, p. 1; @other]`
+static CITATION_GROUP: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[([^\[\]]*@[^\[\]]*)\]").unwrap());
+/// Item in a Pandoc citation group, `prefix -@key, locator`
+static CITATION_ITEM: LazyLock<Regex> =
+	LazyLock::new(|| Regex::new(r"^\s*([^@]*?)\s*(-?)@(\w(?:[\w:.#$%&+?~/-]*\w)?)(?:\s*,\s*(.*?))?\s*$").unwrap());
+/// Pandoc narrative citation, `@key`
+static CITATION: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(^|[^\w@])@(\w(?:[\w:.#$%&+?~/-]*\w)?)").unwrap());
+/// LaTeX reference to an equation in text, `\eqref{label}` or `\ref{label}`
+static REFERENCE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\\(?:eq)?ref\{([^{}]+)\}").unwrap());
+/// MyST citation role before the inline code with keys, `{cite:t}`
+static CITE_ROLE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{cite(?::(\w+))?\}$").unwrap());
+/// Keys in the bibliography files, `None` without bibliography
+static CITATION_KEYS: LazyLock<Option<HashSet<String>>> = LazyLock::new(|| {
+	let files = BIBLIOGRAPHY.get().filter(|files| !files.is_empty())?;
+	let bib_key = Regex::new(r"(?m)^\s*@\w+\s*\{\s*([^,\s]+)\s*,").unwrap();
+	let mut keys = HashSet::new();
+	for file in files {
+		let content = fs::read_to_string(file).expect("Failed to read bibliography");
+		match file.extension().and_then(|e| e.to_str()) {
+			Some("yml" | "yaml") => keys.extend(
+				serde_yaml_ng::from_str::<serde_json::Value>(&content)
+					.expect("Failed to parse bibliography")
+					.as_object()
+					.into_iter()
+					.flat_map(|entries| entries.keys().cloned()),
+			),
+			_ => keys.extend(bib_key.captures_iter(&content).map(|c| c[1].to_string())),
+		}
+	}
+	Some(keys)
+});
+/// GitHub emoji shortcode, `:rocket:`
+static SHORTCODE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r":([\w+-]+):").unwrap());
+/// Attribute in fenced code meta, `linenos`, `.numberLines` or `hl_lines="2-3"`
+static CODE_ATTRIBUTE: LazyLock<Regex> =
+	LazyLock::new(|| Regex::new(r#"([.#]?[\w-]+)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s}]+)))?"#).unwrap());
+/// Pandoc attribute with the id of a figure, `{#fig-id}`
+static FIGURE_ID: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\s*\{#([\w:.-]+)\}\s*$").unwrap());
+/// <canonical path, labels> of notebooks converted in the same batch, shared by all threads
+static NOTEBOOKS: LazyLock<Mutex<HashMap<PathBuf, HashSet<String>>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+thread_local! {
+	/// <identifier, content> of footnote definitions in the notebook
+	static FOOTNOTE_DEFINITIONS: RefCell<HashMap<String, Vec<Node>>> = RefCell::new(HashMap::new());
+	/// <identifier, label> of footnotes already emitted, later references point to them. The label is `None` while
+	/// the definition is converted, so it can't reference itself
+	static FOOTNOTES_USED: RefCell<HashMap<String, Option<String>>> = RefCell::new(HashMap::new());
+	/// <identifier, definition> of link and image reference definitions in the notebook
+	static LINK_DEFINITIONS: RefCell<HashMap<String, Definition>> = RefCell::new(HashMap::new());
+	/// <identifier, definition> of link and image reference definitions in the cell being converted, they take
+	/// precedence
+	static CELL_LINK_DEFINITIONS: RefCell<HashMap<String, Definition>> = RefCell::new(HashMap::new());
+	/// <heading slug, times used>, for unique heading labels
+	static HEADING_SLUGS: RefCell<HashMap<String, usize>> = RefCell::new(HashMap::new());
+	/// Labels of cells and headings in the notebook being converted
+	static LABELS: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+	/// <name in attachments, file path>
+	static ATTACHMENTS: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+}
 
 /// Convert Markdown to Typst.
 ///
@@ -22,30 +109,204 @@ static ATTACHMENTS: LazyLock<Mutex<HashMap<String, String>>> = LazyLock::new(||
 /// - `md` (`&str`) - Markdown string
 /// - `attachments` (`HashMap<String, String>`) - Peprocessed attachments with <name, file_path>
 pub fn md_to_typst(md: &str, attachments: HashMap<String, String>) -> String {
-	let tree = to_mdast(
-		md,
-		&ParseOptions {
-			constructs: Constructs {
-				math_flow: true,
-				math_text: true,
-				..Constructs::gfm()
-			},
-			..Default::default()
-		},
-	)
-	.unwrap();
+	// footnote and link references are only parsed with a definition in the same document, add definitions from other
+	// cells
+	let local = to_mdast(&colon_fence(md), &parse_options()).unwrap();
+	let stubs = footnote_stubs(md, &footnote_grep(&local));
+	let cell_definitions = definition_grep(&local);
+	let link_stubs = LINK_DEFINITIONS.with_borrow(|definitions| {
+		let md = normalize_identifier(md);
+		definitions
+			.values()
+			.filter(|definition| {
+				!cell_definitions.contains_key(&definition.identifier)
+					&& md.contains(&format!("[{}]", definition.identifier))
+			})
+			.map(|definition| {
+				format!(
+					"\n\n[{}]: <{}>{}",
+					definition.label.as_ref().unwrap_or(&definition.identifier),
+					definition.url,
+					definition
+						.title
+						.as_ref()
+						.map(|title| format!(" \"{}\"", typ::escape_string(title)))
+						.unwrap_or_default()
+				)
+			})
+			.join("")
+	});
+	let mut tree = to_mdast(&format!("{}{stubs}{link_stubs}", colon_fence(md)), &parse_options()).unwrap();
+	cite_role(&mut tree);
 
 	// Write tree to debug file
 	// let mut file = File::create("debug.txt").unwrap();
 	// file.write_all(format!("{:#?}", tree).as_bytes()).unwrap();
-	{
-		let mut w_fd = FOOTNOTE_DEFINITIONS.lock().unwrap();
-		*w_fd = footnote_grep(&tree);
+	FOOTNOTE_DEFINITIONS.with_borrow_mut(|definitions| {
+		for (id, definition) in footnote_grep(&tree) {
+			definitions.entry(id).or_insert(definition);
+		}
+	});
+	LINK_DEFINITIONS.with_borrow_mut(|definitions| {
+		for (id, definition) in definition_grep(&tree) {
+			definitions.entry(id).or_insert(definition);
+		}
+	});
+	ATTACHMENTS.set(attachments);
+	CELL_LINK_DEFINITIONS.set(cell_definitions);
+	let typst = ast_parse(&tree).to_string();
+	CELL_LINK_DEFINITIONS.with_borrow_mut(HashMap::clear);
+	typst
+}
+
+fn parse_options() -> ParseOptions {
+	ParseOptions {
+		constructs: Constructs {
+			gfm_autolink_literal: true,
+			math_flow: true,
+			math_text: true,
+			..Constructs::gfm()
+		},
+		..Default::default()
+	}
+}
+
+/// Definition of a link or image reference, in the cell being converted or else anywhere in the notebook.
+fn link_definition(identifier: &str) -> Option<Definition> {
+	CELL_LINK_DEFINITIONS
+		.with_borrow(|definitions| definitions.get(identifier).cloned())
+		.or_else(|| LINK_DEFINITIONS.with_borrow(|definitions| definitions.get(identifier).cloned()))
+}
+
+/// Stub definitions for footnotes referenced in `md` and defined only in other cells.
+fn footnote_stubs(md: &str, local: &HashMap<String, Vec<Node>>) -> String {
+	FOOTNOTE_DEFINITIONS.with_borrow(|definitions| {
+		FOOTNOTE_REFERENCE
+			.captures_iter(md)
+			.map(|c| c.extract::<1>().1[0])
+			.filter(|label| {
+				let id = normalize_identifier(label);
+				definitions.contains_key(&id) && !local.contains_key(&id)
+			})
+			.unique()
+			.map(|label| format!("\n\n[^{label}]: stub"))
+			.join("")
+	})
+}
+
+/// Returns the YAML front matter at the start of Markdown and the Markdown after it.
+///
+/// Only the first cell can have front matter, other cells would lose a thematic break and what follows.
+pub fn front_matter(md: &str) -> Option<(String, &str)> {
+	let mut options = parse_options();
+	options.constructs.frontmatter = true;
+	match to_mdast(md, &options).unwrap().children()?.first()? {
+		Node::Yaml(node) => Some((node.value.clone(), &md[node.position.as_ref()?.end.offset..])),
+		_ => None,
+	}
+}
+
+/// Start converting a notebook with labels of its cells and headings.
+pub fn set_labels(labels: HashSet<String>) {
+	HEADING_SLUGS.with_borrow_mut(HashMap::clear);
+	LABELS.set(labels);
+}
+
+/// Start converting a notebook with footnote and link definitions in all of its Markdown cells.
+pub fn set_definitions<'a>(mds: impl IntoIterator<Item = &'a str>) {
+	let mut footnotes = HashMap::new();
+	let mut links = HashMap::new();
+	for md in mds {
+		let tree = to_mdast(md, &parse_options()).unwrap();
+		for (id, definition) in footnote_grep(&tree) {
+			footnotes.entry(id).or_insert(definition);
+		}
+		for (id, definition) in definition_grep(&tree) {
+			links.entry(id).or_insert(definition);
+		}
+	}
+	FOOTNOTE_DEFINITIONS.set(footnotes);
+	FOOTNOTES_USED.with_borrow_mut(HashMap::clear);
+	LINK_DEFINITIONS.set(links);
+}
+
+/// Register labels of a notebook converted in the same batch.
+pub fn register_notebook(path: &Path, labels: HashSet<String>) {
+	if let Ok(path) = fs::canonicalize(path) {
+		NOTEBOOKS.lock().unwrap().insert(path, labels);
+	}
+}
+
+/// Collect heading, figure and equation labels in Markdown, `slugs` is shared by all cells in the notebook.
+pub fn md_labels(md: &str, slugs: &mut HashMap<String, usize>) -> Vec<String> {
+	fn grep(node: &Node, slugs: &mut HashMap<String, usize>, labels: &mut Vec<String>) {
+		if let Node::Heading(node) = node {
+			labels.extend(heading_label(node, slugs));
+		} else if let Node::Paragraph(node) = node
+			&& FIGURES.get().copied().unwrap_or_default()
+			&& let Some((_, _, Some(id))) = figure(node)
+		{
+			labels.push(id);
+		} else if let Node::Math(node) = node {
+			labels.extend(katex::equation_labels(&node.value));
+		} else if let Some(children) = node.children() {
+			for child in children {
+				grep(child, slugs, labels);
+			}
+		}
+	}
 
-		let mut w_a = ATTACHMENTS.lock().unwrap();
-		*w_a = attachments;
+	let mut labels = Vec::new();
+	grep(&to_mdast(md, &parse_options()).unwrap(), slugs, &mut labels);
+	labels
+}
+
+/// Unique label of a heading, repeated slugs are suffixed with a count like GitHub.
+fn heading_label(node: &Heading, slugs: &mut HashMap<String, usize>) -> Option<String> {
+	let slug = slugify(&node.children.iter().map(Node::to_string).join(""));
+	if slug.is_empty() {
+		return None;
+	}
+	let count = slugs.entry(slug.clone()).or_default();
+	*count += 1;
+	match count {
+		1 => Some(slug),
+		n => Some(format!("{slug}-{}", *n - 1)),
+	}
+}
+
+/// Resolve a link to the `#link` destination, links to the notebook itself or other notebooks in the batch are
+/// resolved to labels.
+fn resolve_link(url: &str) -> Result<String, String> {
+	let find_label = |labels: &HashSet<String>, fragment: &str| {
+		[fragment.to_string(), slugify(fragment)]
+			.into_iter()
+			.find(|label| labels.contains(label))
+			.ok_or_else(|| format!("Unresolved internal link: {url}"))
+	};
+
+	let (path, fragment) = url.split_once('#').unwrap_or((url, ""));
+	if path.is_empty() {
+		return LABELS
+			.with_borrow(|labels| find_label(labels, fragment))
+			.map(|label| format!("<{label}>"));
+	}
+	if Url::parse(url).is_err()
+		&& let Some(stem) = path.strip_suffix(".ipynb")
+	{
+		let notebooks = NOTEBOOKS.lock().unwrap();
+		let Some(labels) = fs::canonicalize(path).ok().and_then(|path| notebooks.get(&path)) else {
+			return Err(format!(
+				"Unresolved notebook link, not converted in the same batch: {url}"
+			));
+		};
+		// compiled output of the other notebook
+		return match fragment {
+			"" => Ok(format!("\"{stem}.pdf\"")),
+			_ => find_label(labels, fragment).map(|label| format!("\"{stem}.pdf#{label}\"")),
+		};
 	}
-	ast_parse(&tree).to_string()
+	Ok(format!("\"{url}\""))
 }
 
 macro_rules! parse_children {
@@ -56,122 +317,589 @@ macro_rules! parse_children {
 
 fn ast_parse(node: &Node) -> Cow<'_, str> {
 	match node {
-		Node::Blockquote(node) => format!(
-			"#block-quote[\n  {}\n]\n",
-			node.children
-				.iter()
-				.map(|child| ast_parse(child).trim_end_matches('\n').replace('\n', "\n  "))
-				.join("\n  ")
-		)
+		Node::Blockquote(node) => match alert(node) {
+			Some((kind, title, children)) => admonition(&kind, title, &children),
+			None => format!(
+				"#block-quote[\n  {}\n]\n",
+				node.children
+					.iter()
+					.map(|child| ast_parse(child).trim_end_matches('\n').replace('\n', "\n  "))
+					.join("\n  ")
+			),
+		}
 		.into(),
 		Node::Break(_) => "\n".into(),
-		Node::Code(node) => format!("```{}\n{}\n```\n", node.lang.as_deref().unwrap_or_default(), node.value).into(),
+		Node::Code(node) => {
+			if let Some(kind) = node
+				.lang
+				.as_deref()
+				.and_then(|lang| lang.strip_prefix('{')?.strip_suffix('}'))
+				.filter(|kind| ADMONITIONS.contains(kind))
+			{
+				// MyST directive, options at the start are skipped
+				let content = node
+					.value
+					.lines()
+					.skip_while(|line| line.starts_with(':') && line[1..].contains(':'))
+					.join("\n");
+				let tree = to_mdast(&colon_fence(&content), &parse_options()).unwrap();
+				admonition(
+					kind,
+					node.meta.as_deref().map(str::trim).filter(|meta| !meta.is_empty()),
+					tree.children().unwrap(),
+				)
+			} else {
+				code_fence(node)
+			}
+			.into()
+		}
+		Node::Definition(_) => "".into(),
 		Node::Delete(node) => format!("#strike[{}]", parse_children!(node)).into(),
 		Node::Emphasis(node) => format!("#emph[{}]", parse_children!(node)).into(),
 		Node::FootnoteDefinition(_) => "".into(),
 		Node::FootnoteReference(node) => {
 			let id = &node.identifier;
-			if let Some(link) = FOOTNOTE_DEFINITIONS.lock().unwrap().get(id) {
-				format!("#link(\"{link}\")[^{id}]")
-			} else {
-				format!("[^{id}]")
+			let definition = FOOTNOTE_DEFINITIONS.with_borrow(|definitions| definitions.get(id).cloned());
+			let used = FOOTNOTES_USED.with_borrow(|used| used.get(id).cloned());
+			match (definition, used) {
+				(Some(_), Some(Some(label))) => format!("#footnote(<{label}>)"),
+				(Some(definition), None) => {
+					// labels are numbered, slugs of different labels can be the same or empty
+					let label = FOOTNOTES_USED.with_borrow_mut(|used| {
+						used.insert(id.clone(), None);
+						format!("fn-{}", used.len())
+					});
+					let content = definition.iter().map(ast_parse).join("\n");
+					FOOTNOTES_USED.with_borrow_mut(|used| used.insert(id.clone(), Some(label.clone())));
+					format!("#footnote[{}]<{label}>", content.trim_end())
+				}
+				// undefined or referenced in its own definition
+				_ => format!("[^{}]", typ::escape_content(id)),
 			}
 			.into()
 		}
-		Node::Heading(node) => format!("{} {}\n\n", "=".repeat(node.depth as usize), parse_children!(node)).into(),
+		Node::Heading(node) => format!(
+			"{} {}{}\n\n",
+			"=".repeat(node.depth as usize),
+			parse_children!(node),
+			HEADING_SLUGS
+				.with_borrow_mut(|slugs| heading_label(node, slugs))
+				.map(|label| format!(" <{label}>"))
+				.unwrap_or_default()
+		)
+		.into(),
 		Node::Html(node) => html_to_typst(&node.value).into(),
-		Node::Image(node) => match Url::parse(&node.url) {
-			Ok(url) => match url.scheme() {
-				"http" | "https" => format!("#image(\"{}\")", download_image(url)).into(),
-				_ => {
-					let name = node.url.strip_prefix("attachment:").unwrap();
-					let a = ATTACHMENTS.lock().unwrap();
-					if let Some(file_path) = a.get(name) {
-						format!("#image(\"{file_path}\")").into()
-					} else {
-						"".into()
-					}
-				}
-			},
-			Err(_) => {
-				let name = node.url.strip_prefix("attachment:").unwrap();
-				if let Some(file_path) = ATTACHMENTS.lock().unwrap().get(name) {
-					format!("#image(\"{file_path}\")").into()
-				} else {
-					"".into()
-				}
-			}
+		Node::ImageReference(node) => match link_definition(&node.identifier) {
+			Some(definition) => ast_parse(&Node::Image(Image {
+				alt: node.alt.clone(),
+				url: definition.url,
+				title: definition.title,
+				position: None,
+			}))
+			.into_owned()
+			.into(),
+			None => typ::escape_content(&node.alt).into(),
 		},
+		Node::Image(node) => image_path(node)
+			.map_or("".into(), |path| format!("#{}", image_parse(node, &path)))
+			.into(),
+		// a backtick would end the raw markup early
+		Node::InlineCode(node) if node.value.contains('`') => {
+			format!("#raw(\"{}\");", typ::escape_string(&node.value)).into()
+		}
 		Node::InlineCode(node) => format!("`{}`", node.value).into(),
-		Node::InlineMath(node) => format!("${}$", katex::latex_to_typst((&node.value).into()).unwrap()).into(),
-		Node::Link(node) => format!("#link(\"{}\")[{}]", node.url, parse_children!(node)).into(),
-		Node::List(node) => format!(
-			"{}\n",
+		Node::InlineMath(node) => katex::inline_math(&node.value).into(),
+		Node::Link(node) => {
+			// an unresolved link still points to its URL
+			let dest = resolve_link(&node.url).unwrap_or_else(|e| {
+				eprintln!("{e}");
+				format!("\"{}\"", node.url)
+			});
+			format!("#link({dest})[{}]", parse_children!(node)).into()
+		}
+		Node::LinkReference(node) => match link_definition(&node.identifier) {
+			Some(definition) => ast_parse(&Node::Link(Link {
+				children: node.children.clone(),
+				url: definition.url,
+				title: definition.title,
+				position: None,
+			}))
+			.into_owned()
+			.into(),
+			None => parse_children!(node).into(),
+		},
+		Node::List(node) => match node.start {
+			// markup can't start an enum at other numbers
+			Some(start) if node.ordered && start != 1 => format!(
+				"#enum(start: {start}, tight: {}){}\n\n",
+				!node.spread,
+				node.children
+					.iter()
+					.map(|child| format!(
+						"[\n  {}\n]",
+						ast_parse(child).trim_end_matches('\n').replace('\n', "\n  ")
+					))
+					.join("")
+			)
+			.into(),
+			_ => format!(
+				"{}\n",
+				node.children
+					.iter()
+					.map(|child| {
+						let mut ret = format!(
+							"{} {}\n",
+							if node.ordered { '+' } else { '-' },
+							ast_parse(child).trim_end_matches('\n').replace('\n', "\n  ")
+						);
+						if node.spread {
+							ret.push('\n');
+						}
+						ret
+					})
+					.join("")
+			)
+			.into(),
+		},
+		Node::ListItem(node) => format!(
+			"{}{}",
+			node.checked
+				.map_or("".into(), |checked| format!("#checkbox({checked}) ")),
+			// blocks in a loose item are separate paragraphs
 			node.children
 				.iter()
-				.map(|child| {
-					let mut ret = format!(
-						"{} {}\n",
-						if node.ordered { '+' } else { '-' },
-						ast_parse(child).trim_end_matches('\n').replace('\n', "\n  ")
-					);
-					if node.spread {
-						ret.push('\n');
-					}
-					ret
-				})
-				.join("")
+				.map(ast_parse)
+				.join(if node.spread { "\n" } else { "" })
 		)
 		.into(),
-		Node::ListItem(node) => parse_children!(node).into(),
-		Node::Math(node) => format!("$ {} $\n", katex::latex_to_typst((&node.value).into()).unwrap()).into(),
-		Node::Paragraph(node) => format!("{}\n", parse_children!(node)).into(),
-		Node::Root(node) => parse_children!(node).into(),
-		Node::Strong(node) => format!("*{}*", parse_children!(node)).into(),
-		Node::Table(node) => format!(
-			"#table(\n  columns: {},\n  align: ({}),\n  table.header(\n    {}  ),\n{})\n\n",
-			node.align.len(),
-			node.align
-				.iter()
-				.map(|a| {
-					match a {
-						markdown::mdast::AlignKind::Left => "left",
-						markdown::mdast::AlignKind::Center => "center",
-						markdown::mdast::AlignKind::Right => "right",
-						markdown::mdast::AlignKind::None => "auto",
-					}
+		Node::Math(node) => katex::block_math(&node.value).into(),
+		Node::Paragraph(node) => {
+			if FIGURES.get().copied().unwrap_or_default()
+				&& let Some((image, caption, id)) = figure(node)
+			{
+				// an image that can't be found is left out, like in a paragraph
+				image_path(&image).map_or("".into(), |path| {
+					format!(
+						"#figure(\n  {},\n  caption: [{}],\n){}\n",
+						image_parse(&image, &path),
+						typ::escape_content(&caption),
+						id.map(|id| format!(" <{id}>")).unwrap_or_default()
+					)
 				})
-				.collect::<Vec<_>>()
-				.join(", "),
-			ast_parse(&node.children[0]),
-			node.children.iter().skip(1).map(|child| ast_parse(child)).join("")
-		)
-		.into(),
+			} else {
+				format!("{}\n", parse_children!(node))
+			}
+			.into()
+		}
+		Node::Root(node) => {
+			let mut ret = String::new();
+			let mut children = node.children.iter().peekable();
+			while let Some(child) = children.next() {
+				// `Table: caption` separated from the table by a blank line
+				if let Node::Table(table) = child
+					&& let Some(Node::Paragraph(paragraph)) = children.peek()
+					&& let Some(caption) = table_caption(&paragraph.children)
+				{
+					children.next();
+					ret += &table_parse(table, Some(caption));
+					continue;
+				}
+				ret += &ast_parse(child);
+			}
+			ret.into()
+		}
+		Node::Strong(node) => format!("*{}*", parse_children!(node)).into(),
+		Node::Table(node) => table_parse(node, None).into(),
 		Node::TableCell(node) => format!("[{}]", parse_children!(node)).into(),
 		Node::TableRow(node) => {
 			format!("  {},\n", node.children.iter().map(|child| ast_parse(child)).join(", ")).into()
 		}
-		Node::Text(node) => typ::escape_content(&node.value).into(),
+		Node::Text(node) => {
+			let text = SHORTCODE.replace_all(&node.value, |c: &Captures| {
+				emojis::get_by_shortcode(&c[1]).map_or(c[0].to_string(), |emoji| emoji.as_str().to_string())
+			});
+			let mut ret = String::with_capacity(text.len());
+			let mut last = 0;
+			for c in REFERENCE.captures_iter(&text) {
+				let range = c.get(0).unwrap().range();
+				ret += &citation_parse(&text[last..range.start]);
+				ret += &match c[1].trim() {
+					label if typ::is_label_name(label) => format!("@{label}"),
+					label => format!("#ref({});", typ::label(label)),
+				};
+				last = range.end;
+			}
+			ret += &citation_parse(&text[last..]);
+			ret.into()
+		}
 		Node::ThematicBreak(_) => "#line(length: 100%)\n".into(),
 		_ => unreachable!(),
 	}
 }
 
-fn footnote_grep(node: &Node) -> HashMap<String, String> {
-	let mut definitions: HashMap<String, String> = HashMap::new();
+/// Convert MyST citation roles to Pandoc citations.
+fn cite_role(node: &mut Node) {
+	let Some(children) = node.children_mut() else {
+		return;
+	};
+	let mut i = 0;
+	while i < children.len() {
+		let citation = match (&children[i], children.get(i + 1)) {
+			(Node::Text(text), Some(Node::InlineCode(code))) => CITE_ROLE.captures(&text.value).map(|role| {
+				let mut keys = code.value.split(',').map(|key| format!("@{}", key.trim()));
+				let citation = match role.get(1).map(|m| m.as_str()) {
+					Some("t" | "ts") => keys.join(", "),
+					_ => format!("[{}]", keys.join("; ")),
+				};
+				format!("{}{citation}", &text.value[..role.get(0).unwrap().start()])
+			}),
+			_ => None,
+		};
+		if let Some(mut value) = citation {
+			children.remove(i + 1);
+			// keep following text together to check what comes after the citation
+			if let Some(Node::Text(text)) = children.get(i + 1) {
+				value += &text.value;
+				children.remove(i + 1);
+			}
+			children[i] = Node::Text(Text { value, position: None });
+		}
+		cite_role(&mut children[i]);
+		i += 1;
+	}
+}
+
+/// Convert Pandoc citations in text, the rest is escaped.
+///
+/// Citations are only converted when their keys are in the bibliography, Typst fails on `#cite` without one.
+fn citation_parse(text: &str) -> String {
+	citations(text, CITATION_KEYS.as_ref())
+}
+
+/// Convert citations with the keys in the bibliography, `None` without bibliography.
+fn citations(text: &str, keys: Option<&HashSet<String>>) -> String {
+	let known = |key: &str| keys.is_some_and(|keys| keys.contains(key));
+	let cite = |key: &str, args: &str| format!("#cite({}{args})", typ::label(key));
+	// text right after a function call could be taken as its arguments
+	let push_text = |ret: &mut String, text: &str| {
+		if ret.ends_with(')') && text.starts_with(['[', '(', '.']) {
+			ret.push(';');
+		}
+		*ret += &typ::escape_content(text);
+	};
+	let narrative = |ret: &mut String, text: &str| {
+		let mut last = 0;
+		for c in CITATION.captures_iter(text) {
+			let key = c.get(2).unwrap();
+			// without bibliography, `@` is mostly in handles and decorators
+			if known(key.as_str()) {
+				push_text(ret, &text[last..key.start() - 1]);
+				*ret += &cite(key.as_str(), ", form: \"prose\"");
+				last = key.end();
+			}
+		}
+		push_text(ret, &text[last..]);
+	};
+
+	if !text.contains('@') {
+		return typ::escape_content(text);
+	}
+	let mut ret = String::with_capacity(text.len() * 2);
+	let mut last = 0;
+	for group in CITATION_GROUP.captures_iter(text) {
+		let Some(items) = group[1]
+			.split(';')
+			.map(|item| CITATION_ITEM.captures(item))
+			.collect::<Option<Vec<_>>>()
+		else {
+			continue;
+		};
+		let range = group.get(0).unwrap().range();
+		narrative(&mut ret, &text[last..range.start]);
+		last = range.end;
+		let unknown = items
+			.iter()
+			.map(|item| &item[3])
+			.filter(|key| !known(key))
+			.collect_vec();
+		if !unknown.is_empty() {
+			if keys.is_some() {
+				eprintln!("Unknown citation key: {}", unknown.iter().join(", "));
+			}
+			push_text(&mut ret, &group[0]);
+			continue;
+		}
+		for item in items {
+			if !item[1].is_empty() {
+				push_text(&mut ret, &format!("{} ", &item[1]));
+			}
+			let mut args = String::new();
+			if &item[2] == "-" {
+				args += ", form: \"year\"";
+			}
+			if let Some(locator) = item.get(4).filter(|m| !m.is_empty()) {
+				write!(args, ", supplement: [{}]", typ::escape_content(locator.as_str())).unwrap();
+			}
+			ret += &cite(&item[3], &args);
+		}
+	}
+	narrative(&mut ret, &text[last..]);
+	ret
+}
+
+/// Convert fenced code, attributes in meta go to `code-fence` in the template.
+fn code_fence(node: &Code) -> String {
+	// fence must be longer than backticks in the code
+	let fence = "`".repeat(
+		node.value
+			.split(|c| c != '`')
+			.map(str::len)
+			.max()
+			.unwrap_or_default()
+			.max(2) + 1,
+	);
+	let raw = format!(
+		"{fence}{}\n{}\n{fence}",
+		node.lang.as_deref().unwrap_or_default(),
+		node.value
+	);
+
+	let mut args = Vec::new();
+	for attribute in CODE_ATTRIBUTE.captures_iter(node.meta.as_deref().unwrap_or_default()) {
+		let value = attribute
+			.get(2)
+			.or(attribute.get(3))
+			.or(attribute.get(4))
+			.map(|m| m.as_str());
+		match (attribute[1].trim_start_matches('.'), value) {
+			("linenos" | "linenums" | "numberLines" | "number-lines", None | Some("true" | "table" | "inline")) => {
+				args.push("linenos: true".into())
+			}
+			("linenostart" | "startFrom" | "start-from" | "start", Some(start)) if start.parse::<usize>().is_ok() => {
+				args.push(format!("start: {start}"))
+			}
+			("hl_lines" | "hl-lines" | "highlight" | "emphasize-lines", Some(lines)) => {
+				let lines = lines
+					.split([' ', ','])
+					.filter_map(|range| match range.split_once('-') {
+						Some((start, end)) => Some(start.parse::<usize>().ok()?..=end.parse().ok()?),
+						None => range.parse().ok().map(|line| line..=line),
+					})
+					.flatten()
+					.join(", ");
+				if !lines.is_empty() {
+					args.push(format!("highlight: ({lines},)"));
+				}
+			}
+			("title" | "caption" | "filename" | "name", Some(title)) => {
+				args.push(format!("title: [{}]", typ::escape_content(title)))
+			}
+			_ => {}
+		}
+	}
+
+	if args.is_empty() {
+		format!("{raw}\n")
+	} else {
+		format!("#code-fence({})[\n{raw}\n]\n", args.join(", "))
+	}
+}
+
+/// Image alone in a paragraph, a figure with `--figures`, with its caption and the id of a following `{#fig-id}` attribute.
+fn figure(node: &Paragraph) -> Option<(Image, String, Option<String>)> {
+	let (image, rest) = node.children.split_first()?;
+	let image = match image {
+		Node::Image(image) => image.clone(),
+		Node::ImageReference(reference) => {
+			let definition = link_definition(&reference.identifier)?;
+			Image {
+				alt: reference.alt.clone(),
+				url: definition.url,
+				title: definition.title,
+				position: None,
+			}
+		}
+		_ => return None,
+	};
+	let id = match rest {
+		[] => None,
+		[Node::Text(text)] if text.value.trim().is_empty() => None,
+		[Node::Text(text)] => Some(FIGURE_ID.captures(&text.value)?[1].to_string()),
+		_ => return None,
+	};
+	let caption = image.title.clone().unwrap_or_else(|| image.alt.clone());
+	(!caption.is_empty()).then_some((image, caption, id))
+}
+
+/// Local path of an image, remote images are downloaded.
+fn image_path(node: &Image) -> Option<String> {
+	match Url::parse(&node.url) {
+		Ok(url) if matches!(url.scheme(), "http" | "https") => Some(download_image(url)),
+		_ => {
+			let name = node.url.strip_prefix("attachment:")?;
+			ATTACHMENTS.with_borrow(|attachments| attachments.get(name).cloned())
+		}
+	}
+}
+
+/// `image` call without `#`, with alt text if any.
+fn image_parse(node: &Image, path: &str) -> String {
+	match node.alt.as_str() {
+		"" => format!("image(\"{path}\")"),
+		alt => format!("image(\"{path}\", alt: \"{}\")", typ::escape_string(alt)),
+	}
+}
+
+/// Convert a table, the first row being the header.
+fn table_parse(node: &Table, caption: Option<String>) -> String {
+	let columns = node.align.len();
+	// ragged rows are padded or truncated to the delimiter row
+	let row_parse = |row: &Node| {
+		let cells = row.children().map_or(&[][..], |cells| cells);
+		let cells = (0..columns)
+			.map(|i| cells.get(i).map_or("[]".into(), ast_parse))
+			.join(", ");
+		format!("  {cells},\n")
+	};
+	let table = format!(
+		"table(\n  columns: {},\n  align: ({}),\n  table.header(\n    {}  ),\n{})",
+		columns,
+		node.align
+			.iter()
+			.map(|a| match a {
+				AlignKind::Left => "left",
+				AlignKind::Center => "center",
+				AlignKind::Right => "right",
+				AlignKind::None => "auto",
+			})
+			.join(", "),
+		node.children.first().map_or("".into(), row_parse),
+		node.children.iter().skip(1).map(row_parse).join("")
+	);
+	match caption {
+		Some(caption) => format!(
+			"#figure(\n  {},\n  caption: [{caption}],\n)\n\n",
+			table.replace('\n', "\n  ")
+		),
+		None => format!("#{table}\n\n"),
+	}
+}
+
+/// Parse a `Table: caption` or `: caption` line following a table.
+fn table_caption(children: &[Node]) -> Option<String> {
+	let Some(Node::Text(text)) = children.first() else {
+		return None;
+	};
+	let value = text.value.trim_start();
+	let rest = value.strip_prefix("Table:").or_else(|| value.strip_prefix(':'))?;
+	let mut children = children.to_vec();
+	children[0] = Node::Text(Text {
+		value: rest.trim_start().into(),
+		position: None,
+	});
+	Some(children.iter().map(ast_parse).join("").trim().into())
+}
+
+/// Split a GitHub alert blockquote into kind, title and content.
+fn alert(node: &Blockquote) -> Option<(String, Option<&str>, Vec<Node>)> {
+	let Some(Node::Paragraph(paragraph)) = node.children.first() else {
+		return None;
+	};
+	let Some(Node::Text(text)) = paragraph.children.first() else {
+		return None;
+	};
+	let captures = ALERT.captures(&text.value)?;
+	let title = captures.get(2).unwrap().as_str().trim();
+
+	let mut paragraph = paragraph.clone();
+	match &text.value[captures.get(0).unwrap().end()..] {
+		"" => {
+			paragraph.children.remove(0);
+		}
+		rest => {
+			paragraph.children[0] = Node::Text(Text {
+				value: rest.into(),
+				position: None,
+			})
+		}
+	}
+	let mut children = node.children.clone();
+	if paragraph.children.is_empty() {
+		children.remove(0);
+	} else {
+		children[0] = Node::Paragraph(paragraph);
+	}
+	Some((
+		captures[1].to_lowercase(),
+		Some(title).filter(|title| !title.is_empty()),
+		children,
+	))
+}
+
+fn admonition(kind: &str, title: Option<&str>, children: &[Node]) -> String {
+	format!(
+		"#admonition(kind: \"{kind}\", title: {})[\n  {}\n]\n",
+		title.map_or("none".into(), |title| format!("[{}]", typ::escape_content(title))),
+		children
+			.iter()
+			.map(|child| ast_parse(child).trim_end_matches('\n').replace('\n', "\n  "))
+			.join("\n\n  ")
+	)
+}
+
+/// Convert MyST colon fences to backtick fences, so they are parsed as code with the directive in `lang`.
+fn colon_fence(md: &str) -> Cow<'_, str> {
+	if !md.contains(":::") {
+		return md.into();
+	}
+
+	let mut ret = String::with_capacity(md.len());
+	let mut lines = md.lines();
+	while let Some(line) = lines.next() {
+		let Some(captures) = COLON_FENCE.captures(line) else {
+			ret += line;
+			ret.push('\n');
+			continue;
+		};
+		let colons = captures[1].len();
+		// nested fences are handled when parsing the content
+		let content = lines
+			.by_ref()
+			.take_while(|line| {
+				let line = line.trim();
+				line.len() < colons || line.chars().any(|c| c != ':')
+			})
+			.join("\n");
+		let backticks = "`".repeat(
+			content
+				.split(|c| c != '`')
+				.map(str::len)
+				.max()
+				.unwrap_or_default()
+				.max(2) + 1,
+		);
+		write!(
+			ret,
+			"{backticks}{{{}}}{}\n{content}\n{backticks}\n",
+			&captures[2], &captures[3]
+		)
+		.unwrap();
+	}
+	ret.into()
+}
+
+fn footnote_grep(node: &Node) -> HashMap<String, Vec<Node>> {
+	let mut definitions: HashMap<String, Vec<Node>> = HashMap::new();
 	match node {
 		Node::FootnoteDefinition(node) => {
-			let mut item = String::new();
-			for child in &node.children {
-				item = footnote_def_parse(child);
-			}
-			definitions.insert(node.identifier.clone(), item);
+			definitions.insert(node.identifier.clone(), node.children.clone());
 		}
 		_ => {
 			if let Some(children) = node.children() {
 				for child in children {
-					let mut d = footnote_grep(child);
-					definitions.extend(d.drain());
+					for (id, definition) in footnote_grep(child) {
+						definitions.entry(id).or_insert(definition);
+					}
 				}
 			}
 		}
@@ -179,13 +907,42 @@ fn footnote_grep(node: &Node) -> HashMap<String, String> {
 	definitions
 }
 
-fn footnote_def_parse(node: &Node) -> String {
+fn definition_grep(node: &Node) -> HashMap<String, Definition> {
+	let mut definitions: HashMap<String, Definition> = HashMap::new();
 	match node {
-		Node::Paragraph(node) => node.children.iter().map(footnote_def_parse).join(""),
-		Node::Text(node) => node.value.to_owned(),
-		Node::Link(node) => node.url.to_owned(),
-		_ => unreachable!("footnote_def_parse unhandled node: {:?}\n", node),
+		Node::Definition(node) => {
+			definitions.insert(node.identifier.clone(), node.clone());
+		}
+		_ => {
+			if let Some(children) = node.children() {
+				for child in children {
+					for (id, definition) in definition_grep(child) {
+						definitions.entry(id).or_insert(definition);
+					}
+				}
+			}
+		}
 	}
+	definitions
+}
+
+/// Normalize a footnote or link label to its identifier, the same as `markdown` does.
+fn normalize_identifier(label: &str) -> String {
+	label.split_whitespace().join(" ").to_lowercase()
+}
+
+/// GitHub style heading slug, also valid as a Typst label.
+fn slugify(text: &str) -> String {
+	text.trim()
+		.chars()
+		.filter_map(|c| match c {
+			c if c.is_whitespace() => Some('-'),
+			'-' | '_' => Some(c),
+			c if c.is_alphanumeric() => Some(c),
+			_ => None,
+		})
+		.flat_map(char::to_lowercase)
+		.collect()
 }
 
 pub fn sha1(s: &str) -> String {
@@ -259,7 +1016,231 @@ mod tests {
 	#[test]
 	fn test_heading_math() {
 		let md = "## heading $math$";
-		assert_eq!(md_to_typst(md, HashMap::new()), "== heading $m a t h$\n\n")
+		assert_eq!(
+			md_to_typst(md, HashMap::new()),
+			"== heading $m a t h$ <heading-math>\n\n"
+		)
+	}
+
+	#[test]
+	fn test_heading_label() {
+		assert_eq!(slugify(" Section 1.2: *Setup* "), "section-12-setup");
+		let md = "# Label Test\n\n# Label Test\n\n[back](#Label-Test) [missing](#missing)";
+		let labels = md_labels(md, &mut HashMap::new());
+		assert_eq!(labels, ["label-test", "label-test-1"]);
+		set_labels(labels.into_iter().collect());
+		assert_eq!(
+			md_to_typst(md, HashMap::new()),
+			"= Label Test <label-test>\n\n= Label Test <label-test-1>\n\n#link(<label-test>)[back] #link(\"#missing\")[missing]\n"
+		);
+	}
+
+	#[test]
+	fn test_notebook_link() {
+		let dir = std::env::temp_dir().join(format!("jupyter2typst-link-{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let other = dir.join("other.ipynb");
+		File::create(&other).unwrap();
+		register_notebook(&other, HashSet::from(["intro".to_string(), "cell-a".to_string()]));
+		let other = other.to_str().unwrap();
+		let stem = other.strip_suffix(".ipynb").unwrap();
+		assert_eq!(resolve_link(other), Ok(format!("\"{stem}.pdf\"")));
+		assert_eq!(
+			resolve_link(&format!("{other}#Intro")),
+			Ok(format!("\"{stem}.pdf#intro\""))
+		);
+		assert!(resolve_link(&format!("{other}#missing")).is_err());
+		// not in the batch
+		let missing = dir.join("missing.ipynb");
+		assert!(resolve_link(missing.to_str().unwrap()).is_err());
+		assert_eq!(
+			resolve_link("https://example.com/a.ipynb"),
+			Ok("\"https://example.com/a.ipynb\"".into())
+		);
+		fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn test_footnote() {
+		set_definitions([
+			"Text[^Note].",
+			"[^note]: Has *emphasis*, `code` and $x$.\n\n    Second paragraph.",
+		]);
+		assert_eq!(
+			md_to_typst("Text[^Note]. Again[^note].", HashMap::new()),
+			"Text#footnote[Has #emph[emphasis], `code` and $x$.\n\nSecond paragraph.]<fn-1>. Again#footnote(<fn-1>).\n"
+		);
+	}
+
+	#[test]
+	fn test_footnote_labels() {
+		// same and empty slugs
+		set_definitions(["[^a!]: One.\n\n[^a?]: Two.\n\n[^!!]: Three.\n\n[^ü]: Four."]);
+		assert_eq!(
+			md_to_typst("A[^a!] b[^a?] c[^!!] d[^ü] e[^a?].", HashMap::new()),
+			"A#footnote[One.]<fn-1> b#footnote[Two.]<fn-2> c#footnote[Three.]<fn-3> d#footnote[Four.]<fn-4> \
+			 e#footnote(<fn-2>).\n"
+		);
+		// referenced in its own definition
+		set_definitions(["[^self]: See[^self] and[^other].\n\n[^other]: Back[^self]."]);
+		assert_eq!(
+			md_to_typst("Text[^self].", HashMap::new()),
+			"Text#footnote[See[^self] and#footnote[Back[^self].]<fn-2>.]<fn-1>.\n"
+		);
+		// undefined, the identifier is escaped
+		assert_eq!(md_to_typst("Text[^a_b*#].", HashMap::new()), "Text[^a\\_b\\*\\#].\n");
+	}
+
+	#[test]
+	fn test_footnote_stubs() {
+		set_definitions(["[^a]: First cell.", "Text[^a][^b].\n\n[^b]: This cell."]);
+		let md = "Text[^a][^b].\n\n[^b]: This cell.";
+		let local = footnote_grep(&to_mdast(md, &parse_options()).unwrap());
+		assert_eq!(footnote_stubs(md, &local), "\n\n[^a]: stub");
+	}
+
+	#[test]
+	fn test_reference() {
+		set_definitions(["[Docs]", "[docs]: https://typst.app/docs \"Typst\""]);
+		assert_eq!(
+			md_to_typst(
+				"[Read][docs], [docs][] and [Docs]. [Local][local]\n\n[local]: #nowhere",
+				HashMap::new()
+			),
+			"#link(\"https://typst.app/docs\")[Read], #link(\"https://typst.app/docs\")[docs] and \
+			 #link(\"https://typst.app/docs\")[Docs]. #link(\"#nowhere\")[Local]\n"
+		);
+		// the cell's own definition comes first
+		set_definitions([
+			"[x]: https://first.example",
+			"[x][]\n\n[x]: https://second.example",
+			"[x][]",
+		]);
+		assert_eq!(
+			md_to_typst("[x][]\n\n[x]: https://second.example", HashMap::new()),
+			"#link(\"https://second.example\")[x]\n"
+		);
+		assert_eq!(
+			md_to_typst("[x][]", HashMap::new()),
+			"#link(\"https://first.example\")[x]\n"
+		);
+	}
+
+	#[test]
+	fn test_list() {
+		assert_eq!(
+			md_to_typst("5. five\n6. six\n\n- [ ] todo\n- [x] done", HashMap::new()),
+			"#enum(start: 5, tight: true)[\n  five\n][\n  six\n]\n\n- #checkbox(false) todo\n- #checkbox(true) done\n\n"
+		);
+		assert_eq!(
+			md_to_typst("1. first\n\n   ```py\n   x = 1\n   ```\n2. second", HashMap::new()),
+			"+ first\n  \n  ```py\n  x = 1\n  ```\n+ second\n\n"
+		);
+	}
+
+	#[test]
+	fn test_admonition() {
+		assert_eq!(
+			md_to_typst("> [!WARNING]\n> Be *careful*.\n>\n> Really.", HashMap::new()),
+			"#admonition(kind: \"warning\", title: none)[\n  Be #emph[careful].\n\n  Really.\n]\n"
+		);
+		assert_eq!(
+			md_to_typst(":::{tip} Title\n:class: x\nUse `x`.\n:::\nAfter", HashMap::new()),
+			"#admonition(kind: \"tip\", title: [Title])[\n  Use `x`.\n]\nAfter\n"
+		);
+		assert_eq!(md_to_typst("> quote", HashMap::new()), "#block-quote[\n  quote\n]\n");
+	}
+
+	#[test]
+	fn test_front_matter() {
+		let md = "---\ntitle: Report\n---\n# Heading";
+		assert_eq!(front_matter(md).unwrap(), ("title: Report".into(), "\n# Heading"));
+		assert!(front_matter("# Heading\n---\n").is_none());
+		// only the first cell has front matter
+		assert_eq!(
+			md_to_typst("---\nSome text\n\n---\n", HashMap::new()),
+			"#line(length: 100%)\nSome text\n#line(length: 100%)\n"
+		);
+	}
+
+	#[test]
+	fn test_citation() {
+		// without bibliography
+		assert_eq!(
+			md_to_typst(
+				"As @doe2019 said [see @smith2020, p. 33; -@doe2019]. {cite:p}`a,b`[x] mail@example.com",
+				HashMap::new()
+			),
+			"As \\@doe2019 said [see \\@smith2020, p. 33; \\-\\@doe2019]. [\\@a; \\@b][x] \
+			 #link(\"mailto:mail@example.com\")[mail\\@example.com]\n"
+		);
+		let keys = HashSet::from(["doe2019".to_string(), "smith2020".to_string()]);
+		assert_eq!(
+			citations("As @doe2019 said [see @smith2020, p. 33; -@doe2019].", Some(&keys)),
+			"As #cite(<doe2019>, form: \"prose\") said see #cite(<smith2020>, supplement: [p. 33])\
+			 #cite(<doe2019>, form: \"year\");."
+		);
+		assert_eq!(
+			citations("[@doe2019][x], @property and [@doe2019; @unknown].", Some(&keys)),
+			"#cite(<doe2019>);[x], \\@property and [\\@doe2019; \\@unknown]."
+		);
+	}
+
+	#[test]
+	fn test_emoji_autolink() {
+		assert_eq!(
+			md_to_typst(
+				"Ship it :rocket: :+1: at 10:30:45 :not_an_emoji: www.example.com",
+				HashMap::new()
+			),
+			"Ship it 🚀 👍 at 10:30:45 :not\\_an\\_emoji: #link(\"http://www.example.com\")[www.example.com]\n"
+		);
+	}
+
+	#[test]
+	fn test_equation_reference() {
+		assert_eq!(
+			md_to_typst(
+				"$$\nx = 1 \\label{eq:x}\n$$\nBy \\eqref{eq:x} and \\ref{eq:x}.",
+				HashMap::new()
+			),
+			"#math.equation(block: true, numbering: \"(1)\", $ x = 1 $) <eq:x>\nBy @eq:x and @eq:x.\n"
+		);
+	}
+
+	#[test]
+	fn test_math_fallback() {
+		assert_eq!(
+			md_to_typst(
+				"Bad $\\frac{a$ (here) and good $x$\n\n$$\n\\left( x\n$$",
+				HashMap::new()
+			),
+			"Bad #latex-error(\"\\\\frac{a\"); (here) and good $x$\n#latex-error(\"\\\\left( x\", block: true)\n"
+		);
+	}
+
+	#[test]
+	fn test_code_fence() {
+		assert_eq!(
+			md_to_typst(
+				"```python {linenos=true hl_lines=\"2-3 5\" title='main.py'}\nx = 1\n```",
+				HashMap::new()
+			),
+			"#code-fence(linenos: true, highlight: (2, 3, 5,), title: [main.py])[\n```python\nx = 1\n```\n]\n"
+		);
+		assert_eq!(
+			md_to_typst("````md\n```\ncode\n```\n````", HashMap::new()),
+			"````md\n```\ncode\n```\n````\n"
+		);
+		// no line that can be parsed
+		assert_eq!(
+			md_to_typst("```python {hl_lines=\"a-b x\" linenos}\nx = 1\n```", HashMap::new()),
+			"#code-fence(linenos: true)[\n```python\nx = 1\n```\n]\n"
+		);
+		assert_eq!(
+			md_to_typst("```python {emphasize-lines=\"{2}\"}\nx = 1\n```", HashMap::new()),
+			"```python\nx = 1\n```\n"
+		);
 	}
 
 	#[test]
@@ -307,4 +1288,67 @@ mod tests {
 			.to_string(),
 		)
 	}
+
+	#[test]
+	fn test_table_ragged() {
+		let table = "| a | b | c |
+|:--|:-:|--:|
+| `x\\|y` | $x^2$ |
+| 1 | 2 | 3 | 4 |
+
+Table: Some *caption*";
+		assert_eq!(
+			md_to_typst(table, HashMap::new()),
+			"#figure(
+  table(
+    columns: 3,
+    align: (left, center, right),
+    table.header(
+        [a], [b], [c],
+    ),
+    [`x|y`], [$x^2$], [],
+    [1], [2], [3],
+  ),
+  caption: [Some #emph[caption]],
+)
+
+"
+		);
+		assert_eq!(
+			md_to_typst("| a |\n|---|\n\n: Caption", HashMap::new()),
+			"#figure(\n  table(\n    columns: 1,\n    align: (auto),\n    table.header(\n        [a],\n    ),\n  ),\n  caption: [Caption],\n)\n\n"
+		);
+		// a row is not a caption
+		assert_eq!(
+			md_to_typst("| a |\n|---|\n| b |\n| Table: c |", HashMap::new()),
+			"#table(\n  columns: 1,\n  align: (auto),\n  table.header(\n      [a],\n  ),\n  [b],\n  [Table: c],\n)\n\n"
+		);
+	}
+
+	#[test]
+	fn test_figure() {
+		let paragraph = |md: &str| match to_mdast(md, &parse_options()).unwrap().children().unwrap()[0].clone() {
+			Node::Paragraph(paragraph) => paragraph,
+			_ => unreachable!(),
+		};
+		let (image, caption, id) =
+			figure(&paragraph("![A \"plot\"](attachment:a.png \"The *plot*\"){#fig-plot}")).unwrap();
+		assert_eq!(
+			(image.url.as_str(), image.alt.as_str()),
+			("attachment:a.png", "A \"plot\"")
+		);
+		assert_eq!((caption.as_str(), id.as_deref()), ("The *plot*", Some("fig-plot")));
+		assert_eq!(figure(&paragraph("![Alt](a.png)")).unwrap().1, "Alt");
+		assert!(figure(&paragraph("![](a.png)")).is_none());
+		assert!(figure(&paragraph("See ![inline](a.png)")).is_none());
+		assert!(figure(&paragraph("![Alt](a.png) text")).is_none());
+		// without `--figures`
+		assert_eq!(
+			md_to_typst(
+				"![A](attachment:a.png \"The plot\")",
+				HashMap::from([("a.png".to_string(), "img/a.png".to_string())])
+			),
+			"#image(\"img/a.png\", alt: \"A\")\n"
+		);
+	}
 }
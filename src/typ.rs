@@ -28,3 +28,17 @@ pub fn escape_content(s: &str) -> String {
 	}
 	result
 }
+
+/// Whether a label can be written as `<name>` and referenced as `@name`.
+pub fn is_label_name(name: &str) -> bool {
+	!name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || "_-:.".contains(c))
+}
+
+/// Label as a Typst value, `<name>` or `label("name")` when it has other characters.
+pub fn label(name: &str) -> String {
+	if is_label_name(name) {
+		format!("<{name}>")
+	} else {
+		format!("label(\"{}\")", escape_string(name))
+	}
+}
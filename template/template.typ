@@ -22,6 +22,27 @@
   box(height: 0pt, move(dx: -size.width, dy: -size.height - inset, c))
 }
 
+#let code-fence(body, linenos: false, start: 1, highlight: (), title: none) = {
+  show raw.line: it => {
+    let line = if linenos {
+      box(width: 2em, align(right, text(fill: luma(120), str(it.number + start - 1))))
+      h(0.8em)
+      it.body
+    } else {
+      it.body
+    }
+    if it.number in highlight {
+      box(fill: yellow.lighten(60%), outset: (y: 2pt), line)
+    } else {
+      line
+    }
+  }
+  if title != none {
+    block(fill: luma(210), inset: (x: inset, y: inset / 2), radius: (top: radius), below: 0pt, text(size: 0.9em, title))
+  }
+  block(fill: luma(240), inset: inset, radius: if title != none { (bottom: radius) } else { radius }, width: 100%, body)
+}
+
 #let result-block(body) = {
   v(0pt, weak: true)
   ansi-render(body, radius: radius, inset: inset, width: 100%)
@@ -49,7 +70,92 @@
   )
 }
 
-#let template(body) = {
+#let admonition(body, kind: "note", title: none) = {
+  let color = (
+    note: blue,
+    seealso: blue,
+    tip: green,
+    hint: green,
+    important: purple,
+    warning: orange,
+    attention: orange,
+    caution: red,
+    danger: red,
+    error: red,
+  ).at(kind, default: luma(120))
+  block(
+    fill: color.lighten(90%),
+    stroke: (left: 4pt + color),
+    inset: inset,
+    radius: (right: radius),
+    width: 100%,
+  )[
+    #text(fill: color.darken(20%), weight: "bold", if title != none { title } else { upper(kind.first()) + kind.slice(1) }) \
+    #body
+  ]
+}
+
+#let checkbox(checked) = box(
+  width: 0.8em,
+  height: 0.8em,
+  stroke: 0.5pt,
+  radius: 1pt,
+  baseline: 0.1em,
+  if checked {
+    align(center + horizon, text(size: 0.7em, sym.checkmark))
+  },
+)
+
+// PDF can't be embedded directly, replace this to include it in other ways
+#let pdf-image(path, fallback: none) = {
+  if fallback != none {
+    image(fallback)
+  } else {
+    block(
+      stroke: (paint: luma(150), dash: "dashed"),
+      inset: inset,
+      radius: radius,
+      raw(path),
+    )
+  }
+}
+
+#let interactive-output(kind, title: none, summary: "") = block(
+  fill: luma(245),
+  stroke: (paint: luma(150), dash: "dashed"),
+  inset: inset,
+  radius: radius,
+  width: 100%,
+)[
+  #text(fill: luma(100))[Interactive #kind output omitted]
+  #if title != none [: *#title*] \
+  #text(size: 0.9em, summary)
+]
+
+// LaTeX math that couldn't be converted, kept as source
+#let latex-error(source, block: false) = {
+  let source = text(fill: red.darken(20%), raw(source, lang: "latex"))
+  if block {
+    align(center, source)
+  } else {
+    source
+  }
+}
+
+#let template(body, title: none, authors: (), date: none, abstract: none) = {
   set page(height: auto)
+  set document(author: authors)
+  if title != none {
+    align(center, text(size: 1.6em, weight: "bold", title))
+  }
+  if authors != () {
+    align(center, authors.join(", "))
+  }
+  if date != none {
+    align(center, date)
+  }
+  if abstract != none {
+    pad(x: 2em)[*Abstract.* #abstract]
+  }
   body
 }
\ No newline at end of file
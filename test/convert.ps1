@@ -1,7 +1,7 @@
 pushd $PSScriptRoot
 
-(cat ../template/template.typ -raw) -replace [regex]::Escape('#let template(body) = {'),
-	"#let template(body) = {`n  set document(date: none)" `
+(cat ../template/template.typ -raw) -replace [regex]::Escape('#let template(body, title: none, authors: (), date: none, abstract: none) = {'),
+	"#let template(body, title: none, authors: (), date: none, abstract: none) = {`n  set document(date: none)" `
 	> ./template.typ
 
 ls *.ipynb | % {
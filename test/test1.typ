@@ -2,7 +2,7 @@
 #show: template
 
 #block[
-= Jupyter Notebook files
+= Jupyter Notebook files <jupyter-notebook-files>
 
 You can create content with Jupyter notebooks.
 For example, the content for the current page is contained in {download}`this notebook file <./notebooks.ipynb>`.
@@ -14,7 +14,7 @@ See [](./myst-notebooks.md) for more details.
 Jupyter Book supports all Markdown that is supported by Jupyter Notebook.
 This is mostly a flavour of Markdown called #link("https://commonmark.org/")[CommonMark Markdown] with minor modifications.
 For more information about writing Jupyter\-flavoured Markdown in Jupyter Book, see #link("./markdown.md")[].
-== Code blocks and image outputs
+== Code blocks and image outputs <code-blocks-and-image-outputs>
 
 Jupyter Book will also embed your code blocks and output in your book.
 For example, here\'s some sample Matplotlib code:
@@ -89,7 +89,7 @@ check out the {ref}`layout/sidebar` section.
 ```
 ]
 #block[
-== Removing content before publishing
+== Removing content before publishing <removing-content-before-publishing>
 
 You can also remove some content before publishing your book to the web.
 For reference, {download}`you can download the notebook content for this page <notebooks.ipynb>`.
@@ -144,7 +144,7 @@ pd.DataFrame([['hi', 'there'], ['this', 'is'], ['a', 'DataFrame']], columns=['Wo
 See {ref}`hiding/remove-content` for more information about hiding and removing content.
 ]
 #block[
-== Interactive outputs
+== Interactive outputs <interactive-outputs>
 
 We can do the same for #emph[interactive] material. Below we\'ll display a map
 using #link("https://python-visualization.github.io/folium/")[folium]. When your book is built,
@@ -188,7 +188,7 @@ m"
 #result-block("<folium.folium.Map at 0x22bff8ea690>")
 ]
 #block[
-== Rich outputs from notebook cells
+== Rich outputs from notebook cells <rich-outputs-from-notebook-cells>
 
 ]
 #block[
@@ -221,7 +221,7 @@ Cell [1;32mIn[9], line 1[0m
 [1;31mNameError[0m: name 'this_will_error' is not defined")
 ]
 #block[
-== More features with Jupyter notebooks
+== More features with Jupyter notebooks <more-features-with-jupyter-notebooks>
 
 There are many other features of Jupyter notebooks to take advantage of,
 such as automatically generating Binder links for notebooks or connecting your content with a kernel in the cloud.
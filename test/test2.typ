@@ -2,9 +2,9 @@
 #show: template
 
 #block[
-= 1.
+= 1. <1>
 
-== (a)
+== (a) <a>
 
 ]
 #block[
@@ -23,7 +23,7 @@ plot(p, [I.(p), I.(1 .- p), H.(p)], label=[\"I(p)\" \"I(1-p)\" \"H(p)\"])"
 #image("./img/985daaaacb50fe430f2a5eae9d74119bb590aea2.svg")
 ]
 #block[
-== (b)
+== (b) <b>
 
 ]
 #block[
@@ -38,14 +38,14 @@ surface(p, p, H)"
 #image("./img/e7da6cb820adc923140956e22a8c0d372f812a89.svg")
 ]
 #block[
-= 2.
+= 2. <2>
 
 The derivative image has a lower entropy than the original image, because most of its pixel values are close to zero and have a high probability. This means that the derivative image contains less information per pixel than the original image, and therefore it can be compressed more efficiently.
 ]
 #block[
-= 3.
+= 3. <3>
 
-== (a)
+== (a) <a-1>
 
 ]
 #block[
@@ -70,7 +70,7 @@ plot!(quantize(f, bit, -1, 1), -1, 1, label=[\"quantize(f, $bit)\" \"error\"], l
 #image("./img/c99fc14339dd048ab2beb09d5d14b5bc121b9d0d.svg")
 ]
 #block[
-== (b)
+== (b) <b-1>
 
 ]
 #block[
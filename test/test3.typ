@@ -8,29 +8,29 @@ default(fmt = :png)
 
 using DataFrames"
 , lang: "julia", count: 2)
-]
+] <cell-26b33cee>
 #block[
 ]
 #block[
-== Using Plots.jl
+== Using Plots.jl <using-plotsjl>
 
 Plots.jl outputs plots in different formats. It is written in #link("https://julialang.org")[Julia]:
-#image("img/e4f510a108a52350c25b6485f4c9058cdae2ccba.png")
-#image("img/c75b0d358982c06f338e70a3759a053a212d8278.png")
-]
+#image("img/e4f510a108a52350c25b6485f4c9058cdae2ccba.png", alt: "image1.png")
+#image("img/c75b0d358982c06f338e70a3759a053a212d8278.png", alt: "image.png")
+] <cell-9ab2ada9>
 #block[
 #code-block("f(x) = sin(x)
 g(x) = cos(x)
 h(x) = tan(x)"
 , lang: "julia", count: 21)
-]
+] <cell-85bd78ed>
 #block[
 #result-block("h (generic function with 1 method)")
 ]
 #block[
 #code-block("xs = LinRange(0, 2pi, 100)"
 , lang: "julia", count: 22)
-]
+] <cell-37a89891>
 #block[
 #result-block("100-element LinRange{Float64, Int64}:
  0.0, 0.0634665, 0.126933, 0.1904, …, 6.09279, 6.15625, 6.21972, 6.28319")
@@ -41,22 +41,22 @@ $sin(x)$
 $cos(x)$
 $tan(x)$
 According to Wikipedia, their graphs look like this:
-#image("img/e4f510a108a52350c25b6485f4c9058cdae2ccba.png")
-]
+#image("img/e4f510a108a52350c25b6485f4c9058cdae2ccba.png", alt: "image.png")
+] <cell-63f92630>
 #block[
 #code-block("plot(xs, [f, g, h]; ylim = (-2, 2), framestyle = :box, grid = false, palette = :tab10)"
 , lang: "julia", count: 23)
-]
+] <cell-5af82814>
 #block[
 #image("./img/ea844af1262c9c7267aaed6e3f6bb2a54b115ac9.png")
 ]
 #block[
 Let\'s produce an error:
-]
+] <cell-a33baaeb>
 #block[
 #code-block("i(x)"
 , lang: "julia", count: 24)
-]
+] <cell-82b95d53>
 #block[
 #result-block("UndefVarError: `i` not defined
 
@@ -65,14 +65,14 @@ Stacktrace:
    @ In[24]:1")
 ]
 #block[
-== Rich Outputs
+== Rich Outputs <rich-outputs>
 
 We can try some table outputs, for example:
-]
+] <cell-2fb78fbc>
 #block[
 #code-block("df = DataFrame((col1 = [\"First\", \"Second\", \"Third\"], col2 = [1, 2, 3]))"
 , lang: "julia", count: 3)
-]
+] <cell-ae37e722>
 #block[
 #result-block("[1m3×2 DataFrame[0m
 [1m Row [0m│[1m col1   [0m[1m col2  [0m
@@ -2,17 +2,17 @@
 #show: template
 
 #block[
-= h1
+= h1 <h1>
 
-== h2
+== h2 <h2>
 
-=== h3
+=== h3 <h3>
 
-==== h4
+==== h4 <h4>
 
-===== h5
+===== h5 <h5>
 
-====== h6
+====== h6 <h6>
 
 \#\#\#\#\#\#\# h7
 *bold*
@@ -47,12 +47,12 @@
 + list
 + list
   #link("https://www.google.com")[link]
-  #image("img/7e70d3c88dbf90fadf0da23862f0113ea3e96d91.png")
+  #image("img/7e70d3c88dbf90fadf0da23862f0113ea3e96d91.png", alt: "image")
   `code`
 
 #line(length: 100%)
 #line(length: 100%)
-Here is a simple footnote#link("https://www.example.com")[^1]. With some additional text after it#link("./out.typ no")[^2].
+Here is a simple footnote#footnote[#link("https://www.example.com")[https:\/\/www.example.com]]<fn-1>. With some additional text after it#footnote[.\/out.typ no]<fn-2>.
 ```
 code
 ```
@@ -71,7 +71,7 @@ block
   [table], [table], [table],
 )
 
-= Table
+= Table <table>
 
 #table(
   columns: 5,
@@ -84,7 +84,7 @@ block
   [Paragraph], [Text], [BBBB], [XXX], [XXX],
 )
 
-== Pretty table
+== Pretty table <pretty-table>
 
 #table(
   columns: 2,
@@ -92,10 +92,10 @@ block
   table.header(
       [Header 1], [Header 2],
   ),
-  [#image("img/ab2e0fb48e36657b7719077fe47911c7b5f84884.jpg")], [#image("img/4f3c219d9527429061b3bb90d9f8cb6c53f36556.jpg")],
+  [#image("img/ab2e0fb48e36657b7719077fe47911c7b5f84884.jpg", alt: "Kitty")], [#image("img/4f3c219d9527429061b3bb90d9f8cb6c53f36556.jpg", alt: "Kitty")],
 )
 
-=== Lists
+=== Lists <lists>
 
 #line(length: 100%)
 - Inline #emph[code] `aaaaz`
@@ -117,14 +117,14 @@ block
 + c
 
 
-==== Multiline code
+==== Multiline code <multiline-code>
 
 ```
 int method() {
 	return 2137;
 }
 ```
-===== Quotes
+===== Quotes <quotes>
 
 #block-quote[
   aa
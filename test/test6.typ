@@ -8,7 +8,8 @@ overbrace(x+⋯+x) n#[ times]
 rho*3\
 #box(inset: (left: 3pt, right: 3pt), outset: (top: 3pt, bottom: 3pt), fill: red)[$3equiv 5 mod 2$]
 H e l l o L a T e X.\
-dot(sum_0^n)display(binom(0, 1))\
+dot(sum_0^n)
+display(binom(0, 1))\
 #box(inset: (left: 3pt, right: 3pt), outset: (top: 3pt, bottom: 3pt), fill: rgb("#00ff00"))[a=b]\
 ::\
 "::−"\